@@ -0,0 +1,7 @@
+DROP INDEX sessions_family_idx;
+
+ALTER TABLE sessions
+  DROP COLUMN family,
+  DROP COLUMN refresh_token,
+  DROP COLUMN refresh_expiry,
+  DROP COLUMN rotated_at;
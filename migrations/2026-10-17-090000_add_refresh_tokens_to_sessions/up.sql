@@ -0,0 +1,8 @@
+ALTER TABLE sessions
+  ADD COLUMN family varchar(100) NULL,
+  ADD COLUMN refresh_token varchar(100) NULL,
+  ADD COLUMN refresh_expiry timestamp WITHOUT TIME ZONE NULL,
+  ADD COLUMN rotated_at timestamp WITHOUT TIME ZONE NULL,
+  ADD UNIQUE (refresh_token);
+
+CREATE INDEX sessions_family_idx ON sessions (family);
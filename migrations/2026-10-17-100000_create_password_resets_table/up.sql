@@ -0,0 +1,10 @@
+CREATE TABLE password_resets
+(
+  token varchar(100) NOT NULL,
+  account uuid NOT NULL,
+  expiry timestamp WITHOUT TIME ZONE NOT NULL,
+  used_at timestamp WITHOUT TIME ZONE NULL,
+  created_at timestamp WITHOUT TIME ZONE DEFAULT (NOW() AT TIME ZONE 'UTC') NOT NULL,
+  PRIMARY KEY (token),
+  FOREIGN KEY (account) REFERENCES accounts (id) ON DELETE CASCADE
+);
@@ -0,0 +1,18 @@
+ALTER TABLE accounts
+  ADD COLUMN email_verified_at timestamp WITHOUT TIME ZONE NULL;
+
+-- Accounts created before verification existed keep logging in when it is required.
+UPDATE accounts
+  SET email_verified_at = created_at;
+
+CREATE TABLE email_verifications
+(
+  token varchar(100) NOT NULL,
+  account uuid NOT NULL,
+  email varchar(100) NOT NULL,
+  expiry timestamp WITHOUT TIME ZONE NOT NULL,
+  used_at timestamp WITHOUT TIME ZONE NULL,
+  created_at timestamp WITHOUT TIME ZONE DEFAULT (NOW() AT TIME ZONE 'UTC') NOT NULL,
+  PRIMARY KEY (token),
+  FOREIGN KEY (account) REFERENCES accounts (id) ON DELETE CASCADE
+);
@@ -0,0 +1,3 @@
+ALTER TABLE accounts
+  DROP COLUMN failed_login_attempts,
+  DROP COLUMN locked_until;
@@ -0,0 +1,4 @@
+ALTER TABLE accounts
+  ADD COLUMN totp_secret varchar(200) NULL,
+  ADD COLUMN totp_enabled_at timestamp WITHOUT TIME ZONE NULL,
+  ADD COLUMN totp_last_step bigint NULL;
@@ -0,0 +1,12 @@
+CREATE TABLE recovery_codes
+(
+  id uuid NOT NULL,
+  account uuid NOT NULL,
+  code varchar(100) NOT NULL,
+  used_at timestamp WITHOUT TIME ZONE NULL,
+  created_at timestamp WITHOUT TIME ZONE DEFAULT (NOW() AT TIME ZONE 'UTC') NOT NULL,
+  PRIMARY KEY (id),
+  FOREIGN KEY (account) REFERENCES accounts (id) ON DELETE CASCADE
+);
+
+CREATE UNIQUE INDEX recovery_codes_account_code_idx ON recovery_codes (account, code);
@@ -0,0 +1,16 @@
+CREATE TABLE webauthn_credentials
+(
+  id uuid NOT NULL,
+  account uuid NOT NULL,
+  credential_id varchar(1400) NOT NULL,
+  public_key bytea NOT NULL,
+  sign_count bigint DEFAULT 0 NOT NULL,
+  name varchar(150) NULL,
+  last_used_at timestamp WITHOUT TIME ZONE NULL,
+  created_at timestamp WITHOUT TIME ZONE DEFAULT (NOW() AT TIME ZONE 'UTC') NOT NULL,
+  PRIMARY KEY (id),
+  UNIQUE (credential_id),
+  FOREIGN KEY (account) REFERENCES accounts (id) ON DELETE CASCADE
+);
+
+CREATE INDEX webauthn_credentials_account_idx ON webauthn_credentials (account);
@@ -0,0 +1,12 @@
+CREATE TABLE linked_identities
+(
+  provider varchar(50) NOT NULL,
+  subject varchar(255) NOT NULL,
+  account uuid NOT NULL,
+  email varchar(150) NULL,
+  created_at timestamp WITHOUT TIME ZONE DEFAULT (NOW() AT TIME ZONE 'UTC') NOT NULL,
+  PRIMARY KEY (provider, subject),
+  FOREIGN KEY (account) REFERENCES accounts (id) ON DELETE CASCADE
+);
+
+CREATE INDEX linked_identities_account_idx ON linked_identities (account);
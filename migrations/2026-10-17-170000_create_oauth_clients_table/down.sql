@@ -0,0 +1,5 @@
+ALTER TABLE sessions
+  DROP COLUMN client_id,
+  DROP COLUMN scope;
+
+DROP TABLE oauth_clients;
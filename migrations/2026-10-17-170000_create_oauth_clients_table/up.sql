@@ -0,0 +1,18 @@
+CREATE TABLE oauth_clients
+(
+  id varchar(64) NOT NULL,
+  account uuid NOT NULL,
+  name varchar(150) NOT NULL,
+  secret varchar(150) NULL,
+  redirect_uri varchar(2000) NOT NULL,
+  scope varchar(255) NOT NULL,
+  created_at timestamp WITHOUT TIME ZONE DEFAULT (NOW() AT TIME ZONE 'UTC') NOT NULL,
+  PRIMARY KEY (id),
+  FOREIGN KEY (account) REFERENCES accounts (id) ON DELETE CASCADE
+);
+
+CREATE INDEX oauth_clients_account_idx ON oauth_clients (account);
+
+ALTER TABLE sessions
+  ADD COLUMN client_id varchar(64) NULL REFERENCES oauth_clients (id) ON DELETE CASCADE,
+  ADD COLUMN scope varchar(255) NULL;
@@ -0,0 +1,17 @@
+CREATE TABLE api_keys
+(
+  id uuid NOT NULL,
+  account uuid NOT NULL,
+  name varchar(150) NOT NULL,
+  prefix varchar(16) NOT NULL,
+  secret varchar(64) NOT NULL,
+  scope varchar(255) NOT NULL,
+  last_used_at timestamp WITHOUT TIME ZONE NULL,
+  expiry timestamp WITHOUT TIME ZONE NULL,
+  created_at timestamp WITHOUT TIME ZONE DEFAULT (NOW() AT TIME ZONE 'UTC') NOT NULL,
+  PRIMARY KEY (id),
+  UNIQUE (prefix),
+  FOREIGN KEY (account) REFERENCES accounts (id) ON DELETE CASCADE
+);
+
+CREATE INDEX api_keys_account_idx ON api_keys (account);
@@ -0,0 +1,3 @@
+DROP TABLE account_roles;
+DROP TABLE role_permissions;
+DROP TABLE roles;
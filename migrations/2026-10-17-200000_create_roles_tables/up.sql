@@ -0,0 +1,31 @@
+CREATE TABLE roles
+(
+  name varchar(50) NOT NULL,
+  description varchar(255) NULL,
+  created_at timestamp WITHOUT TIME ZONE DEFAULT (NOW() AT TIME ZONE 'UTC') NOT NULL,
+  PRIMARY KEY (name)
+);
+
+CREATE TABLE role_permissions
+(
+  role varchar(50) NOT NULL,
+  permission varchar(100) NOT NULL,
+  PRIMARY KEY (role, permission),
+  FOREIGN KEY (role) REFERENCES roles (name) ON DELETE CASCADE
+);
+
+CREATE TABLE account_roles
+(
+  account uuid NOT NULL,
+  role varchar(50) NOT NULL,
+  created_at timestamp WITHOUT TIME ZONE DEFAULT (NOW() AT TIME ZONE 'UTC') NOT NULL,
+  PRIMARY KEY (account, role),
+  FOREIGN KEY (account) REFERENCES accounts (id) ON DELETE CASCADE,
+  FOREIGN KEY (role) REFERENCES roles (name) ON DELETE CASCADE
+);
+
+INSERT INTO roles (name, description)
+  VALUES ('admin', 'Manages every account');
+
+INSERT INTO role_permissions (role, permission)
+  VALUES ('admin', 'accounts:read'), ('admin', 'accounts:write');
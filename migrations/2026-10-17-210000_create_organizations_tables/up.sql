@@ -0,0 +1,40 @@
+CREATE TABLE organizations
+(
+  id uuid NOT NULL,
+  name varchar(150) NOT NULL,
+  created_at timestamp WITHOUT TIME ZONE DEFAULT (NOW() AT TIME ZONE 'UTC') NOT NULL,
+  updated_at timestamp WITHOUT TIME ZONE NULL,
+  PRIMARY KEY (id)
+);
+
+CREATE TABLE memberships
+(
+  organization uuid NOT NULL,
+  account uuid NOT NULL,
+  role varchar(50) NOT NULL CHECK (role IN ('owner', 'admin', 'member')),
+  created_at timestamp WITHOUT TIME ZONE DEFAULT (NOW() AT TIME ZONE 'UTC') NOT NULL,
+  updated_at timestamp WITHOUT TIME ZONE NULL,
+  PRIMARY KEY (organization, account),
+  FOREIGN KEY (organization) REFERENCES organizations (id) ON DELETE CASCADE,
+  FOREIGN KEY (account) REFERENCES accounts (id) ON DELETE CASCADE
+);
+
+CREATE INDEX memberships_account_idx ON memberships (account);
+
+CREATE TABLE invitations
+(
+  id uuid NOT NULL,
+  token varchar(100) NOT NULL,
+  organization uuid NOT NULL,
+  email varchar(150) NOT NULL,
+  role varchar(50) NOT NULL CHECK (role IN ('owner', 'admin', 'member')),
+  invited_by uuid NULL,
+  expiry timestamp WITHOUT TIME ZONE NOT NULL,
+  created_at timestamp WITHOUT TIME ZONE DEFAULT (NOW() AT TIME ZONE 'UTC') NOT NULL,
+  PRIMARY KEY (id),
+  UNIQUE (token),
+  FOREIGN KEY (organization) REFERENCES organizations (id) ON DELETE CASCADE,
+  FOREIGN KEY (invited_by) REFERENCES accounts (id) ON DELETE SET NULL
+);
+
+CREATE INDEX invitations_organization_idx ON invitations (organization);
@@ -0,0 +1,27 @@
+DROP POLICY invitations_select ON invitations;
+ALTER TABLE invitations DISABLE ROW LEVEL SECURITY;
+
+DROP POLICY organizations_select ON organizations;
+ALTER TABLE organizations DISABLE ROW LEVEL SECURITY;
+
+DROP POLICY memberships_delete ON memberships;
+DROP POLICY memberships_select ON memberships;
+ALTER TABLE memberships DISABLE ROW LEVEL SECURITY;
+
+DROP POLICY api_keys_delete ON api_keys;
+DROP POLICY api_keys_update ON api_keys;
+DROP POLICY api_keys_insert ON api_keys;
+DROP POLICY api_keys_select ON api_keys;
+ALTER TABLE api_keys DISABLE ROW LEVEL SECURITY;
+
+DROP POLICY sessions_update ON sessions;
+DROP POLICY sessions_select ON sessions;
+ALTER TABLE sessions DISABLE ROW LEVEL SECURITY;
+
+DROP POLICY accounts_update ON accounts;
+DROP POLICY accounts_select ON accounts;
+ALTER TABLE accounts DISABLE ROW LEVEL SECURITY;
+
+-- The role is shared by every database of the cluster, so it stays and only loses its grants here.
+REVOKE ALL ON accounts, sessions, api_keys, memberships, organizations, invitations FROM api_tenant;
+REVOKE api_tenant FROM CURRENT_USER;
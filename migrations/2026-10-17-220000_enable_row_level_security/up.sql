@@ -0,0 +1,91 @@
+-- Requests run their transactions as api_tenant with the app.* settings applied, see sql::tenant.
+-- The policies only apply to that role and compare every row against the settings, so a
+-- transaction that forgets to set them sees nothing. Logins and other work done before a tenant
+-- is known run as the table owner, which row-level security leaves alone.
+DO $$
+BEGIN
+  IF NOT EXISTS (SELECT FROM pg_roles WHERE rolname = 'api_tenant') THEN
+    CREATE ROLE api_tenant NOLOGIN;
+  END IF;
+END
+$$;
+
+GRANT api_tenant TO CURRENT_USER;
+
+-- Only the tables below are granted, each with the least the role needs. Tables without policies
+-- stay out of reach. Members see the rest of their active organization, but only ever change
+-- rows of their own account.
+GRANT SELECT, UPDATE ON accounts TO api_tenant;
+GRANT SELECT, UPDATE ON sessions TO api_tenant;
+GRANT SELECT, INSERT, UPDATE, DELETE ON api_keys TO api_tenant;
+GRANT SELECT, DELETE ON memberships TO api_tenant;
+GRANT SELECT ON organizations TO api_tenant;
+GRANT SELECT ON invitations TO api_tenant;
+
+ALTER TABLE accounts ENABLE ROW LEVEL SECURITY;
+
+CREATE POLICY accounts_select ON accounts FOR SELECT TO api_tenant
+  USING (
+    id = NULLIF(current_setting('app.account_id', true), '')::uuid
+    OR id IN (
+      SELECT account
+        FROM memberships
+        WHERE organization = NULLIF(current_setting('app.organization_id', true), '')::uuid
+    )
+  );
+
+CREATE POLICY accounts_update ON accounts FOR UPDATE TO api_tenant
+  USING (id = NULLIF(current_setting('app.account_id', true), '')::uuid)
+  WITH CHECK (id = NULLIF(current_setting('app.account_id', true), '')::uuid);
+
+ALTER TABLE sessions ENABLE ROW LEVEL SECURITY;
+
+CREATE POLICY sessions_select ON sessions FOR SELECT TO api_tenant
+  USING (account = NULLIF(current_setting('app.account_id', true), '')::uuid);
+
+CREATE POLICY sessions_update ON sessions FOR UPDATE TO api_tenant
+  USING (account = NULLIF(current_setting('app.account_id', true), '')::uuid)
+  WITH CHECK (account = NULLIF(current_setting('app.account_id', true), '')::uuid);
+
+ALTER TABLE api_keys ENABLE ROW LEVEL SECURITY;
+
+CREATE POLICY api_keys_select ON api_keys FOR SELECT TO api_tenant
+  USING (account = NULLIF(current_setting('app.account_id', true), '')::uuid);
+
+CREATE POLICY api_keys_insert ON api_keys FOR INSERT TO api_tenant
+  WITH CHECK (account = NULLIF(current_setting('app.account_id', true), '')::uuid);
+
+CREATE POLICY api_keys_update ON api_keys FOR UPDATE TO api_tenant
+  USING (account = NULLIF(current_setting('app.account_id', true), '')::uuid)
+  WITH CHECK (account = NULLIF(current_setting('app.account_id', true), '')::uuid);
+
+CREATE POLICY api_keys_delete ON api_keys FOR DELETE TO api_tenant
+  USING (account = NULLIF(current_setting('app.account_id', true), '')::uuid);
+
+ALTER TABLE memberships ENABLE ROW LEVEL SECURITY;
+
+CREATE POLICY memberships_select ON memberships FOR SELECT TO api_tenant
+  USING (
+    account = NULLIF(current_setting('app.account_id', true), '')::uuid
+    OR organization = NULLIF(current_setting('app.organization_id', true), '')::uuid
+  );
+
+-- Leaving is the only change members make themselves, joining goes through an invitation.
+CREATE POLICY memberships_delete ON memberships FOR DELETE TO api_tenant
+  USING (account = NULLIF(current_setting('app.account_id', true), '')::uuid);
+
+ALTER TABLE organizations ENABLE ROW LEVEL SECURITY;
+
+CREATE POLICY organizations_select ON organizations FOR SELECT TO api_tenant
+  USING (
+    id IN (
+      SELECT organization
+        FROM memberships
+        WHERE account = NULLIF(current_setting('app.account_id', true), '')::uuid
+    )
+  );
+
+ALTER TABLE invitations ENABLE ROW LEVEL SECURITY;
+
+CREATE POLICY invitations_select ON invitations FOR SELECT TO api_tenant
+  USING (organization = NULLIF(current_setting('app.organization_id', true), '')::uuid);
@@ -0,0 +1,2 @@
+ALTER TABLE sessions DROP CONSTRAINT sessions_impersonator_fkey;
+ALTER TABLE sessions DROP COLUMN impersonator;
@@ -0,0 +1,5 @@
+-- RESTRICT keeps staff accounts with impersonation sessions from being deleted. SET NULL would
+-- turn those sessions into ordinary ones of the impersonated account, CASCADE would erase them.
+ALTER TABLE sessions ADD COLUMN impersonator uuid NULL;
+ALTER TABLE sessions
+  ADD CONSTRAINT sessions_impersonator_fkey FOREIGN KEY (impersonator) REFERENCES accounts (id) ON DELETE RESTRICT;
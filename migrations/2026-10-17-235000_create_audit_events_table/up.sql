@@ -0,0 +1,36 @@
+-- Accounts are referenced without foreign keys, events outlive the accounts they mention.
+CREATE TABLE audit_events
+(
+  id uuid NOT NULL,
+  event varchar(100) NOT NULL,
+  account uuid NULL,
+  actor uuid NULL,
+  ip varchar(45) NULL,
+  detail text NULL,
+  created_at timestamp WITHOUT TIME ZONE DEFAULT (NOW() AT TIME ZONE 'UTC') NOT NULL,
+  PRIMARY KEY (id)
+);
+
+-- Tenant transactions have no business with the log, admins read it on the API's own role.
+REVOKE ALL ON audit_events FROM api_tenant;
+
+CREATE INDEX audit_events_created_at_idx ON audit_events (created_at);
+CREATE INDEX audit_events_account_idx ON audit_events (account, created_at);
+CREATE INDEX audit_events_event_idx ON audit_events (event, created_at);
+
+CREATE FUNCTION audit_events_append_only() RETURNS trigger AS $$
+BEGIN
+  RAISE EXCEPTION 'audit_events is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_events_append_only
+  BEFORE UPDATE OR DELETE ON audit_events
+  FOR EACH ROW EXECUTE PROCEDURE audit_events_append_only();
+
+CREATE TRIGGER audit_events_no_truncate
+  BEFORE TRUNCATE ON audit_events
+  FOR EACH STATEMENT EXECUTE PROCEDURE audit_events_append_only();
+
+INSERT INTO role_permissions (role, permission)
+  VALUES ('admin', 'audit:read');
@@ -0,0 +1,115 @@
+use crate::{
+    audit::{self, Event},
+    auth::AuthError,
+    environment::Environment,
+    model,
+    session::Session,
+};
+use uuid::Uuid;
+
+/// Most accounts a search returns.
+const SEARCH_LIMIT: i32 = 100;
+
+pub fn search_limit(first: Option<i32>) -> i64 {
+    first.unwrap_or(20).max(1).min(SEARCH_LIMIT) as i64
+}
+
+async fn record(env: &Environment, session: &Session, event: Event, account: Uuid) {
+    audit::record(
+        env,
+        event,
+        audit::Entry {
+            account: Some(account),
+            actor: Some(session.actor()),
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+/// Staff may act on any account but their own and those of other admins, whose privileges
+/// would be handed over or taken away.
+pub fn check_target(admin: Uuid, account: Uuid, roles: &[String]) -> Result<(), AuthError> {
+    if account == admin || roles.iter().any(|role| role == "admin") {
+        return Err(AuthError::Forbidden);
+    }
+
+    Ok(())
+}
+
+/// Disables the account and revokes its sessions, API keys stop working with it. Admins can't
+/// disable themselves or each other, so there is always one left to undo it.
+pub async fn disable(
+    env: &Environment,
+    session: &Session,
+    account: Uuid,
+) -> anyhow::Result<model::Account> {
+    let roles = crate::sql::role::get_account_roles(env.database(), account).await?;
+    check_target(session.account_id(), account, &roles)?;
+
+    let disabled = crate::sql::account::set_disabled(env.database(), account, true).await?;
+    crate::session::revoke_account(env, account).await?;
+    record(env, session, Event::AccountDisabled, account).await;
+
+    Ok(disabled)
+}
+
+pub async fn enable(
+    env: &Environment,
+    session: &Session,
+    account: Uuid,
+) -> anyhow::Result<model::Account> {
+    let enabled = crate::sql::account::set_disabled(env.database(), account, false).await?;
+    record(env, session, Event::AccountEnabled, account).await;
+
+    Ok(enabled)
+}
+
+pub async fn force_password_reset(
+    env: &Environment,
+    session: &Session,
+    account: Uuid,
+) -> anyhow::Result<()> {
+    crate::password::force_reset(env, account).await?;
+    record(env, session, Event::PasswordResetForced, account).await;
+
+    Ok(())
+}
+
+pub async fn revoke_sessions(
+    env: &Environment,
+    session: &Session,
+    account: Uuid,
+) -> anyhow::Result<usize> {
+    let revoked = crate::session::revoke_account(env, account).await?;
+    record(env, session, Event::SessionsRevoked, account).await;
+
+    Ok(revoked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search_limit_is_bounded() {
+        assert_eq!(search_limit(None), 20);
+        assert_eq!(search_limit(Some(0)), 1);
+        assert_eq!(search_limit(Some(1000)), SEARCH_LIMIT as i64);
+    }
+
+    #[test]
+    fn admins_cannot_disable_themselves() {
+        let admin = Uuid::new_v4();
+
+        assert!(check_target(admin, admin, &[]).is_err());
+    }
+
+    #[test]
+    fn admins_cannot_disable_admins() {
+        let roles = vec!["support".to_owned(), "admin".to_owned()];
+
+        assert!(check_target(Uuid::new_v4(), Uuid::new_v4(), &roles).is_err());
+        assert!(check_target(Uuid::new_v4(), Uuid::new_v4(), &roles[..1]).is_ok());
+    }
+}
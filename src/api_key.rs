@@ -0,0 +1,120 @@
+use crate::{
+    auth::AuthError,
+    environment::Environment,
+    helpers::token,
+    model,
+    session::Session,
+    sql::api_key::{ApiKeyByPrefix, NewApiKey},
+};
+use chrono::{DateTime, Utc};
+use ring::constant_time;
+use uuid::Uuid;
+
+/// Marks API keys, so they can be told apart from OAuth access tokens.
+pub const KEY_PREFIX: &str = "wak_";
+/// Characters of the key kept in plain text to look it up.
+const PREFIX_LENGTH: usize = 12;
+
+pub struct CreatedApiKey {
+    pub api_key: model::ApiKey,
+    pub key: String,
+}
+
+/// Creates a key of the form `wak_<prefix>_<secret>`, returned in plain text only here.
+pub async fn create(
+    env: &Environment,
+    session: &Session,
+    name: &str,
+    scope: &str,
+    expiry: Option<DateTime<Utc>>,
+) -> anyhow::Result<CreatedApiKey> {
+    let scope = crate::oauth::scope(scope, &crate::oauth::SCOPES.join(" "))?;
+    let (prefix, key) = generate();
+
+    let api_key = crate::sql::api_key::create_api_key(
+        env.database(),
+        session.account_id(),
+        NewApiKey {
+            id: Uuid::new_v4(),
+            name,
+            prefix: &prefix,
+            secret: &token::hash(&key),
+            scope: &scope,
+            expiry,
+        },
+    )
+    .await?;
+
+    Ok(CreatedApiKey { api_key, key })
+}
+
+pub async fn revoke(env: &Environment, session: &Session, id: Uuid) -> anyhow::Result<bool> {
+    Ok(crate::sql::api_key::delete_api_key(env.database(), session.account_id(), id).await? > 0)
+}
+
+/// Looks up an unexpired key and checks its secret.
+pub async fn verify(env: &Environment, key: &str) -> anyhow::Result<ApiKeyByPrefix> {
+    let prefix = prefix(key).ok_or(AuthError::InvalidCredentials)?;
+
+    let api_key = crate::sql::api_key::get_api_key_by_prefix(env.database(), prefix)
+        .await?
+        .ok_or(AuthError::InvalidCredentials)?;
+    check_secret(&api_key.secret, key)?;
+
+    if let Err(err) = crate::sql::api_key::touch_api_key(env.database(), api_key.id).await {
+        tracing::error!("could not record API key use: {:#}", err);
+    }
+
+    Ok(api_key)
+}
+
+/// Returns the prefix to look the key up by and the key itself.
+fn generate() -> (String, String) {
+    let prefix = token::generate()[..PREFIX_LENGTH].to_owned();
+    let key = format!("{}{}_{}", KEY_PREFIX, prefix, token::generate());
+
+    (prefix, key)
+}
+
+fn prefix(key: &str) -> Option<&str> {
+    key.strip_prefix(KEY_PREFIX)
+        .and_then(|rest| rest.split('_').next())
+}
+
+/// Compares the stored digest with the digest of the whole presented key in constant time.
+fn check_secret(secret: &str, key: &str) -> Result<(), AuthError> {
+    constant_time::verify_slices_are_equal(secret.as_bytes(), token::hash(key).as_bytes())
+        .or(Err(AuthError::InvalidCredentials))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_found_by_their_prefix() {
+        let (stored, key) = generate();
+        assert!(key.starts_with(KEY_PREFIX));
+        assert_eq!(prefix(&key), Some(stored.as_str()));
+    }
+
+    #[test]
+    fn other_tokens_have_no_prefix() {
+        assert_eq!(prefix("eyJhbGciOiJIUzI1NiJ9.e30.sig"), None);
+    }
+
+    #[test]
+    fn verifies_the_whole_key() {
+        let (_, key) = generate();
+        let secret = token::hash(&key);
+        assert!(check_secret(&secret, &key).is_ok());
+
+        let forged = format!(
+            "{}{}",
+            &key[..KEY_PREFIX.len() + PREFIX_LENGTH + 1],
+            "guessed"
+        );
+        assert_eq!(prefix(&forged), prefix(&key));
+        assert!(check_secret(&secret, &forged).is_err());
+    }
+}
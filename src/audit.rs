@@ -0,0 +1,72 @@
+use crate::{environment::Environment, sql::audit_event::NewAuditEvent};
+use std::net::IpAddr;
+use uuid::Uuid;
+
+/// Most events a page of the audit log holds.
+const PAGE_LIMIT: i32 = 200;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Event {
+    LoginSucceeded,
+    LoginFailed,
+    AccountCreated,
+    AccountUpdated,
+    SessionRevoked,
+    AccountDisabled,
+    AccountEnabled,
+    PasswordResetForced,
+    SessionsRevoked,
+    ImpersonationStarted,
+    ImpersonationStopped,
+}
+
+impl Event {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Event::LoginSucceeded => "login.succeeded",
+            Event::LoginFailed => "login.failed",
+            Event::AccountCreated => "account.created",
+            Event::AccountUpdated => "account.updated",
+            Event::SessionRevoked => "session.revoked",
+            Event::AccountDisabled => "admin.account_disabled",
+            Event::AccountEnabled => "admin.account_enabled",
+            Event::PasswordResetForced => "admin.password_reset_forced",
+            Event::SessionsRevoked => "admin.sessions_revoked",
+            Event::ImpersonationStarted => "admin.impersonation_started",
+            Event::ImpersonationStopped => "admin.impersonation_stopped",
+        }
+    }
+}
+
+/// Who and what an event is about, every part is optional.
+#[derive(Default, Debug)]
+pub struct Entry<'a> {
+    pub account: Option<Uuid>,
+    pub actor: Option<Uuid>,
+    pub ip: Option<IpAddr>,
+    pub detail: Option<&'a str>,
+}
+
+/// Appends an event to the audit log. Failures are logged instead of failing the action being
+/// recorded, which has usually happened already.
+pub async fn record(env: &Environment, event: Event, entry: Entry<'_>) {
+    let result = crate::sql::audit_event::create_audit_event(
+        env.database(),
+        NewAuditEvent {
+            event: event.as_str(),
+            account: entry.account,
+            actor: entry.actor,
+            ip: entry.ip.map(|ip| ip.to_string()),
+            detail: entry.detail,
+        },
+    )
+    .await;
+
+    if let Err(err) = result {
+        tracing::error!("could not record audit event {}: {:#}", event.as_str(), err);
+    }
+}
+
+pub fn page_limit(first: Option<i32>) -> i64 {
+    first.unwrap_or(50).max(1).min(PAGE_LIMIT) as i64
+}
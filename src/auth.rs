@@ -1,16 +1,23 @@
 use crate::{
+    audit::{self, Event},
     environment::Environment,
+    helpers::{rate_limit, token},
     model::{self, session::Identity},
+    sql::account::NewSession,
+    two_factor::PendingLogin,
 };
 use chrono::{Duration, Utc};
-use rand::{distributions::Alphanumeric, Rng};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
 use shrinkwraprs::Shrinkwrap;
+use sqlx::types::Json;
 use std::net::SocketAddr;
 use thiserror::Error;
+use uuid::Uuid;
 use warp::{self, http, Reply};
 
+/// Key prefix of the sessions derived from API keys, followed by the key id.
+pub const API_KEY_SESSION: &str = "api_key:";
+
 #[derive(Shrinkwrap, Clone, Serialize, Deserialize, Debug)]
 pub struct Session(model::Session);
 
@@ -21,6 +28,53 @@ pub struct Request {
     lifetime: Option<i64>,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RefreshRequest {
+    refresh_token: String,
+    lifetime: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MagicLinkQuery {
+    token: String,
+    lifetime: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TwoFactorRequest {
+    challenge: String,
+    code: String,
+}
+
+/// Returned instead of tokens when the account requires a second factor, to be completed at
+/// `/auth/2fa`.
+#[derive(Serialize, Debug)]
+pub struct Challenge {
+    challenge: String,
+    methods: Vec<&'static str>,
+}
+
+enum Login {
+    Session(Tokens),
+    TwoFactor(Challenge),
+}
+
+impl Reply for Login {
+    fn into_response(self) -> warp::reply::Response {
+        match self {
+            Login::Session(tokens) => reply(tokens).into_response(),
+            Login::TwoFactor(challenge) => warp::reply::json(&challenge).into_response(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Tokens {
+    jwt: String,
+    csrf: String,
+    refresh_token: String,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Claims {
     session: String,
@@ -28,9 +82,39 @@ pub struct Claims {
 }
 
 impl Claims {
+    /// Claims of a new session with fresh session key and CSRF token.
+    pub fn generate() -> Self {
+        Self {
+            session: token::generate(),
+            csrf: token::generate(),
+        }
+    }
+
     pub fn session(&self) -> String {
         self.session.to_owned()
     }
+
+    pub fn csrf(&self) -> String {
+        self.csrf.to_owned()
+    }
+}
+
+/// What a request authenticates with.
+#[derive(Debug, Clone)]
+pub enum Credentials {
+    /// A login JWT from the `authorization` header or `jwt` cookie, with its CSRF token.
+    Session { jwt: String, csrf: String },
+    /// A token sent as `Authorization: Bearer`, never attached by browsers on their own.
+    Bearer(String),
+}
+
+impl Credentials {
+    pub fn into_session(self) -> Option<(String, String)> {
+        match self {
+            Credentials::Session { jwt, csrf } => Some((jwt, csrf)),
+            Credentials::Bearer(_) => None,
+        }
+    }
 }
 
 #[allow(dead_code)]
@@ -40,16 +124,214 @@ pub enum AuthError {
     InvalidCredentials,
     #[error("could not hash password")]
     ArgonError,
+    #[error("email address not verified")]
+    EmailNotVerified,
+    #[error("insufficient scope")]
+    InsufficientScope,
+    #[error("permission denied")]
+    Forbidden,
+    #[error("no active organization")]
+    NoOrganization,
+    #[error("account disabled")]
+    AccountDisabled,
+    #[error("not allowed while impersonating")]
+    Impersonating,
 }
 
 pub async fn filter(
     env: Environment,
     req: Request,
     address: Option<SocketAddr>,
+) -> anyhow::Result<warp::reply::Response> {
+    Ok(request(env, req, address).await?.into_response())
+}
+
+/// Logs in with a magic link, still asking for the second factor when the account has one.
+pub async fn magic_link(
+    env: Environment,
+    query: MagicLinkQuery,
+    address: Option<SocketAddr>,
+) -> anyhow::Result<warp::reply::Response> {
+    let account = crate::magic_link::consume(&env, &query.token).await?;
+
+    let identity = Identity {
+        fingerprint: None,
+        ip: address.map(|addr| addr.ip()),
+    };
+
+    let totp = crate::sql::totp::get_totp(env.database(), account).await?;
+    if totp.totp_enabled_at.is_some() {
+        let pending = PendingLogin {
+            account,
+            lifetime: query.lifetime,
+            identity,
+        };
+        let challenge = crate::two_factor::challenge(&env, &pending).await?;
+
+        return Ok(warp::reply::json(&Challenge {
+            challenge,
+            methods: vec!["totp", "recovery_code"],
+        })
+        .into_response());
+    }
+
+    let tokens = issue(&env, account, identity, query.lifetime, None).await?;
+
+    Ok(reply(tokens).into_response())
+}
+
+pub async fn two_factor(env: Environment, req: TwoFactorRequest) -> anyhow::Result<impl Reply> {
+    let pending = crate::two_factor::pending(&env, &req.challenge).await?;
+    let verified = crate::two_factor::verify_any(&env, pending.account, &req.code).await;
+    audit::record(
+        &env,
+        match verified {
+            Ok(_) => Event::LoginSucceeded,
+            Err(_) => Event::LoginFailed,
+        },
+        audit::Entry {
+            account: Some(pending.account),
+            ip: pending.identity.ip,
+            detail: Some("second factor"),
+            ..Default::default()
+        },
+    )
+    .await;
+    verified?;
+    crate::two_factor::complete(&env, &req.challenge).await?;
+
+    let tokens = issue(
+        &env,
+        pending.account,
+        pending.identity,
+        pending.lifetime,
+        None,
+    )
+    .await?;
+
+    Ok(reply(tokens))
+}
+
+pub async fn webauthn_register_begin(
+    env: Environment,
+    jwt: String,
+    csrf: String,
+) -> anyhow::Result<impl Reply> {
+    let session = crate::session::Session::new(env.clone(), &jwt, &csrf).await?;
+    session.require_first_party()?;
+    session.require_not_impersonating()?;
+    let options = crate::webauthn::begin_registration(&env, &session).await?;
+
+    Ok(warp::reply::json(&options))
+}
+
+pub async fn webauthn_register_finish(
+    env: Environment,
+    jwt: String,
+    csrf: String,
+    req: crate::webauthn::Registration,
+) -> anyhow::Result<impl Reply> {
+    let session = crate::session::Session::new(env.clone(), &jwt, &csrf).await?;
+    session.require_first_party()?;
+    session.require_not_impersonating()?;
+    crate::webauthn::finish_registration(&env, &session, req).await?;
+
+    Ok(warp::reply::with_status(
+        warp::reply(),
+        http::StatusCode::NO_CONTENT,
+    ))
+}
+
+pub async fn webauthn_login_begin(
+    env: Environment,
+    req: crate::webauthn::LoginRequest,
+) -> anyhow::Result<impl Reply> {
+    let options = crate::webauthn::begin_login(&env, req).await?;
+
+    Ok(warp::reply::json(&options))
+}
+
+pub async fn webauthn_login_finish(
+    env: Environment,
+    req: crate::webauthn::Assertion,
+    address: Option<SocketAddr>,
+) -> anyhow::Result<impl Reply> {
+    let (account, user_verified) = crate::webauthn::finish_login(&env, &req).await?;
+
+    let identity = Identity {
+        fingerprint: None,
+        ip: address.map(|addr| addr.ip()),
+    };
+    if !user_verified {
+        return second_factor(&env, account, identity, req.lifetime).await;
+    }
+    let tokens = issue(&env, account, identity, req.lifetime, None).await?;
+
+    Ok(Login::Session(tokens))
+}
+
+/// Redirects the browser to the identity provider, binding the login to the browser with a cookie.
+pub async fn oidc_start(env: Environment, provider: String) -> anyhow::Result<impl Reply> {
+    let (url, state) = crate::oidc::start(&env, &provider).await?;
+
+    let reply = warp::reply::with_status(warp::reply(), http::StatusCode::FOUND);
+    let reply = warp::reply::with_header(reply, http::header::LOCATION, url);
+
+    Ok(warp::reply::with_header(
+        reply,
+        http::header::SET_COOKIE,
+        crate::oidc::state_cookie(&state),
+    ))
+}
+
+/// Logs in with a linked identity, the provider's login doesn't stand in for the second factor.
+pub async fn oidc_callback(
+    env: Environment,
+    provider: String,
+    callback: crate::oidc::Callback,
+    state: Option<String>,
+    address: Option<SocketAddr>,
+) -> anyhow::Result<impl Reply> {
+    let account = crate::oidc::callback(&env, &provider, callback, state.as_deref()).await?;
+
+    if env.require_verified_email() {
+        let account = crate::sql::account::get_account_by_id(env.database(), account).await?;
+        if account.email_verified_at.is_none() {
+            return Err(AuthError::EmailNotVerified.into());
+        }
+    }
+
+    let identity = Identity {
+        fingerprint: None,
+        ip: address.map(|addr| addr.ip()),
+    };
+
+    // Appended, `with_header` would replace the session cookie.
+    let mut response = second_factor(&env, account, identity, None)
+        .await?
+        .into_response();
+    response.headers_mut().append(
+        http::header::SET_COOKIE,
+        http::HeaderValue::from_str(&crate::oidc::state_cookie(""))?,
+    );
+
+    Ok(response)
+}
+
+pub async fn refresh(
+    env: Environment,
+    req: RefreshRequest,
+    address: Option<SocketAddr>,
 ) -> anyhow::Result<impl Reply> {
-    let (jwt, csrf) = request(env, req, address).await?;
+    let tokens = rotate(env, req, address).await?;
+
+    Ok(reply(tokens))
+}
 
-    let reply = warp::reply::json(&json!({ "jwt": jwt, "csrf": csrf }));
+fn reply(tokens: Tokens) -> impl Reply {
+    let cookie = format!("jwt={}", tokens.jwt);
+
+    let reply = warp::reply::json(&tokens);
     let reply = warp::reply::with_status(reply, http::StatusCode::OK);
 
     let reply = warp::reply::with_header(
@@ -58,7 +340,15 @@ pub async fn filter(
         http_api_problem::PROBLEM_JSON_MEDIA_TYPE,
     );
 
-    let reply = warp::reply::with_header(reply, http::header::SET_COOKIE, format!("jwt={}", jwt));
+    warp::reply::with_header(reply, http::header::SET_COOKIE, cookie)
+}
+
+pub async fn logout(env: Environment, jwt: String, csrf: String) -> anyhow::Result<impl Reply> {
+    let session = session(env.clone(), &jwt, &csrf).await?;
+    crate::session::revoke(&env, &session.key).await?;
+
+    let reply = warp::reply::with_status(warp::reply(), http::StatusCode::NO_CONTENT);
+    let reply = warp::reply::with_header(reply, http::header::SET_COOKIE, "jwt=; Max-Age=0");
 
     Ok(reply)
 }
@@ -67,10 +357,34 @@ async fn request(
     env: Environment,
     req: Request,
     address: Option<SocketAddr>,
-) -> anyhow::Result<(String, String)> {
-    let account = crate::sql::account::get_account_id_password_by_email(env.database(), &req.email)
-        .await?
-        .ok_or(AuthError::InvalidCredentials)?;
+) -> anyhow::Result<Login> {
+    throttle(&env, &req.email, address).await?;
+
+    let identity = Identity {
+        fingerprint: None,
+        ip: address.map(|addr| addr.ip()),
+    };
+
+    let account =
+        match crate::sql::account::get_account_id_password_by_email(env.database(), &req.email)
+            .await?
+        {
+            Some(account) => account,
+            None => {
+                // The address stays out of the log, it may be a password typed in the wrong field.
+                audit::record(
+                    &env,
+                    Event::LoginFailed,
+                    audit::Entry {
+                        ip: identity.ip,
+                        detail: Some("unknown email"),
+                        ..Default::default()
+                    },
+                )
+                .await;
+                return Err(AuthError::InvalidCredentials.into());
+            }
+        };
 
     let is_valid = env
         .argon()
@@ -81,39 +395,229 @@ async fn request(
         .or(Err(AuthError::ArgonError))?;
 
     if !is_valid {
+        crate::sql::account::record_failed_login(
+            env.database(),
+            account.id,
+            env.lockout_threshold(),
+            env.lockout_duration(),
+        )
+        .await?;
+        audit::record(
+            &env,
+            Event::LoginFailed,
+            audit::Entry {
+                account: Some(account.id),
+                ip: identity.ip,
+                detail: Some("invalid password"),
+                ..Default::default()
+            },
+        )
+        .await;
         return Err(AuthError::InvalidCredentials.into());
     }
 
+    // A locked account is refused like a wrong password, so the lock reveals neither the account
+    // nor that its password was right.
+    if let Some(locked_until) = account.locked_until {
+        if locked_until > Utc::now() {
+            return Err(AuthError::InvalidCredentials.into());
+        }
+    }
+
+    crate::sql::account::reset_failed_logins(env.database(), account.id).await?;
+
+    // Only told after the password checked out, so it doesn't reveal which addresses exist.
+    if account.disabled_at.is_some() {
+        audit::record(
+            &env,
+            Event::LoginFailed,
+            audit::Entry {
+                account: Some(account.id),
+                ip: identity.ip,
+                detail: Some("account disabled"),
+                ..Default::default()
+            },
+        )
+        .await;
+        return Err(AuthError::AccountDisabled.into());
+    }
+
+    if env.require_verified_email() && account.email_verified_at.is_none() {
+        return Err(AuthError::EmailNotVerified.into());
+    }
+
+    if env.argon().needs_rehash(&account.password) {
+        if let Err(err) =
+            crate::password::rehash(&env, account.id, &account.password, &req.password).await
+        {
+            tracing::error!("could not rehash password: {:#}", err);
+        }
+    }
+
+    if account.totp_enabled_at.is_some() {
+        return challenge(
+            &env,
+            PendingLogin {
+                account: account.id,
+                lifetime: req.lifetime,
+                identity,
+            },
+        )
+        .await;
+    }
+
+    audit::record(
+        &env,
+        Event::LoginSucceeded,
+        audit::Entry {
+            account: Some(account.id),
+            ip: identity.ip,
+            detail: Some("password"),
+            ..Default::default()
+        },
+    )
+    .await;
+    let tokens = issue(&env, account.id, identity, req.lifetime, None).await?;
+
+    Ok(Login::Session(tokens))
+}
+
+/// Asks for the second factor when the account has one, otherwise issues the session right away.
+async fn second_factor(
+    env: &Environment,
+    account: Uuid,
+    identity: Identity,
+    lifetime: Option<i64>,
+) -> anyhow::Result<Login> {
+    let totp = crate::sql::totp::get_totp(env.database(), account).await?;
+    if totp.totp_enabled_at.is_some() {
+        return challenge(
+            env,
+            PendingLogin {
+                account,
+                lifetime,
+                identity,
+            },
+        )
+        .await;
+    }
+
+    let tokens = issue(env, account, identity, lifetime, None).await?;
+
+    Ok(Login::Session(tokens))
+}
+
+async fn challenge(env: &Environment, pending: PendingLogin) -> anyhow::Result<Login> {
+    let challenge = crate::two_factor::challenge(env, &pending).await?;
+
+    Ok(Login::TwoFactor(Challenge {
+        challenge,
+        methods: vec!["totp", "recovery_code"],
+    }))
+}
+
+/// Limits login attempts per email address and per client address, whether they succeed or not.
+async fn throttle(
+    env: &Environment,
+    email: &str,
+    address: Option<SocketAddr>,
+) -> anyhow::Result<()> {
+    let (limit, window) = env.login_rate_limit();
+    let mut redis = env.redis().await?;
+
+    let key = format!("rate_limit:login:email:{}", email.to_lowercase());
+    rate_limit::sliding_window(&mut redis, &key, limit, window).await?;
+
+    if let Some(address) = address {
+        let key = format!("rate_limit:login:ip:{}", address.ip());
+        rate_limit::sliding_window(&mut redis, &key, limit, window).await?;
+    }
+
+    Ok(())
+}
+
+async fn rotate(
+    env: Environment,
+    req: RefreshRequest,
+    address: Option<SocketAddr>,
+) -> anyhow::Result<Tokens> {
+    let refresh_token = token::hash(&req.refresh_token);
+
+    let session =
+        match crate::sql::session::rotate_refresh_token(env.database(), &refresh_token).await? {
+            Some(session) => session,
+            None => {
+                // A rotated token being presented again means it leaked, so the whole login is revoked.
+                if let Some(session) =
+                    crate::sql::session::get_rotated_session(env.database(), &refresh_token).await?
+                {
+                    let family = session.family.unwrap_or(session.key);
+                    tracing::warn!("refresh token reused, revoking session family {}", family);
+                    crate::session::revoke_family(&env, &family).await?;
+                }
+
+                return Err(AuthError::InvalidCredentials.into());
+            }
+        };
+
+    crate::session::purge(&mut env.redis().await?, &session.key).await?;
+
     let identity = Identity {
-        fingerprint: None,
+        fingerprint: session.identity.fingerprint.to_owned(),
         ip: address.map(|addr| addr.ip()),
     };
+    let family = session.family.unwrap_or(session.key);
 
-    let claims = Claims {
-        session: rand::thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(64)
-            .collect(),
-        csrf: rand::thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(64)
-            .collect(),
-    };
+    issue(&env, session.account, identity, req.lifetime, Some(family)).await
+}
+
+async fn issue(
+    env: &Environment,
+    account: Uuid,
+    identity: Identity,
+    lifetime: Option<i64>,
+    family: Option<String>,
+) -> anyhow::Result<Tokens> {
+    // Every login method and refresh ends up here, so none of them gets around a disabled account.
+    if crate::sql::account::get_account_by_id(env.database(), account)
+        .await?
+        .disabled_at
+        .is_some()
+    {
+        return Err(AuthError::AccountDisabled.into());
+    }
+
+    let claims = Claims::generate();
+    let refresh_token = token::generate();
 
     let csrf = claims.csrf.clone();
-    let expiry = Utc::now() + Duration::seconds(env.session_lifetime(req.lifetime));
+    let family = family.unwrap_or_else(|| claims.session.clone());
+    let expiry = Utc::now() + Duration::seconds(env.session_lifetime(lifetime));
+    let refresh_expiry = Utc::now() + Duration::seconds(env.refresh_token_lifetime());
 
     crate::sql::account::create_session(
         env.database(),
-        &claims.session,
-        &claims.csrf,
-        account.id,
+        NewSession {
+            key: &claims.session,
+            csrf: &claims.csrf,
+            family: &family,
+            refresh_token: &token::hash(&refresh_token),
+            expiry,
+            refresh_expiry,
+            client_id: None,
+            scope: None,
+            impersonator: None,
+        },
+        account,
         identity,
-        expiry,
     )
     .await?;
 
-    Ok((env.jwt().encode(claims, expiry)?, csrf))
+    Ok(Tokens {
+        jwt: env.jwt().encode(claims, expiry)?,
+        csrf,
+        refresh_token,
+    })
 }
 
 pub fn claims(env: &Environment, jwt: &str, csrf: &str) -> anyhow::Result<Claims> {
@@ -135,3 +639,26 @@ pub async fn session(env: Environment, jwt: &str, csrf: &str) -> anyhow::Result<
 
     Ok(Session(session.ok_or(AuthError::InvalidCredentials)?))
 }
+
+/// API keys have no stored session, one limited to the key's scope is derived on every request.
+pub async fn api_key_session(env: &Environment, key: &str) -> anyhow::Result<Session> {
+    let api_key = crate::api_key::verify(env, key).await?;
+
+    Ok(Session(model::Session {
+        key: format!("{}{}", API_KEY_SESSION, api_key.id),
+        csrf: String::new(),
+        account: api_key.account,
+        identity: Json(Identity::default()),
+        expiry: api_key.expiry.unwrap_or(chrono::MAX_DATETIME),
+        invalidated: false,
+        created_at: api_key.created_at,
+        updated_at: None,
+        family: None,
+        refresh_token: None,
+        refresh_expiry: None,
+        rotated_at: None,
+        client_id: None,
+        scope: Some(api_key.scope),
+        impersonator: None,
+    }))
+}
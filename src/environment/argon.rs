@@ -39,4 +39,30 @@ impl Argon {
         let verifier = verifier.with_secret_key(&self.secret);
         verifier.to_owned()
     }
+
+    /// Whether `hash` was created with a memory size or iteration count other than the configured
+    /// one. Parameters left unconfigured are not compared.
+    pub fn needs_rehash(&self, hash: &str) -> bool {
+        let params = match hash.split('$').find(|segment| segment.starts_with("m=")) {
+            Some(params) => params,
+            None => return false,
+        };
+
+        let param = |name: &str| {
+            params.split(',').find_map(|param| {
+                let mut pair = param.splitn(2, '=');
+                match (pair.next(), pair.next()) {
+                    (Some(key), Some(value)) if key == name => value.parse::<u32>().ok(),
+                    _ => None,
+                }
+            })
+        };
+
+        let outdated = |configured: Option<u32>, name: &str| match configured {
+            Some(configured) => param(name) != Some(configured),
+            None => false,
+        };
+
+        outdated(self.memory_size, "m") || outdated(self.iterations, "t")
+    }
 }
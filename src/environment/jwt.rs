@@ -1,20 +1,244 @@
-use crate::auth;
+use crate::{auth, Args};
+use biscuit::{
+    jwa::SignatureAlgorithm, jws::Secret, ClaimPresenceOptions, Presence, RegisteredClaims,
+    SingleOrMultiple, TemporalOptions, Validation, ValidationOptions,
+};
+use chrono::{Duration, Utc};
+use ring::signature::{self, EcdsaKeyPair, KeyPair, RsaKeyPair};
+use serde_json::json;
+use std::{fmt, path::Path, sync::Arc};
 
 type DateTimeUtc = chrono::DateTime<chrono::Utc>;
+
+#[derive(Clone)]
+enum Key {
+    Hmac(String),
+    Rsa(Arc<RsaKeyPair>),
+    Ecdsa(Arc<EcdsaKeyPair>),
+}
+
+impl Key {
+    fn load(
+        algorithm: SignatureAlgorithm,
+        secret: &str,
+        path: Option<&Path>,
+    ) -> anyhow::Result<Self> {
+        match (algorithm, path) {
+            (SignatureAlgorithm::HS256, _) => Ok(Key::Hmac(secret.to_owned())),
+            (SignatureAlgorithm::RS256, Some(path)) => {
+                let der = read_der(path)?;
+                let key = RsaKeyPair::from_pkcs8(&der)
+                    .or_else(|_| RsaKeyPair::from_der(&der))
+                    .map_err(|e| anyhow::anyhow!("invalid RSA private key: {}", e))?;
+                Ok(Key::Rsa(Arc::new(key)))
+            }
+            (SignatureAlgorithm::ES256, Some(path)) => {
+                let der = read_der(path)?;
+                let key =
+                    EcdsaKeyPair::from_pkcs8(&signature::ECDSA_P256_SHA256_FIXED_SIGNING, &der)
+                        .map_err(|e| anyhow::anyhow!("invalid ECDSA private key: {}", e))?;
+                Ok(Key::Ecdsa(Arc::new(key)))
+            }
+            (SignatureAlgorithm::RS256, None) | (SignatureAlgorithm::ES256, None) => Err(
+                anyhow::anyhow!("a private key is required to sign with {:?}", algorithm),
+            ),
+            (algorithm, _) => Err(anyhow::anyhow!("unsupported JWT algorithm {:?}", algorithm)),
+        }
+    }
+
+    fn signing(&self) -> Secret {
+        match self {
+            Key::Hmac(secret) => Secret::bytes_from_str(secret),
+            Key::Rsa(key) => Secret::RsaKeyPair(Arc::clone(key)),
+            Key::Ecdsa(key) => Secret::EcdsaKeyPair(Arc::clone(key)),
+        }
+    }
+
+    fn verifying(&self) -> Secret {
+        match self {
+            Key::Hmac(secret) => Secret::bytes_from_str(secret),
+            Key::Rsa(key) => Secret::PublicKey(key.public_key().as_ref().to_vec()),
+            Key::Ecdsa(key) => Secret::PublicKey(key.public_key().as_ref().to_vec()),
+        }
+    }
+
+    /// The public half as a JWK, symmetric secrets are never published.
+    fn jwk(&self) -> Option<serde_json::Value> {
+        let encode = |bytes: &[u8]| base64::encode_config(bytes, base64::URL_SAFE_NO_PAD);
+
+        match self {
+            Key::Hmac(_) => None,
+            Key::Rsa(key) => Some(json!({
+                "kty": "RSA",
+                "use": "sig",
+                "alg": "RS256",
+                "n": encode(key.public_key().modulus().big_endian_without_leading_zero()),
+                "e": encode(key.public_key().exponent().big_endian_without_leading_zero()),
+            })),
+            Key::Ecdsa(key) => {
+                // Uncompressed SEC1 point: 0x04 || x || y
+                let point = key.public_key().as_ref();
+                Some(json!({
+                    "kty": "EC",
+                    "use": "sig",
+                    "alg": "ES256",
+                    "crv": "P-256",
+                    "x": encode(&point[1..33]),
+                    "y": encode(&point[33..65]),
+                }))
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Key::Hmac(_) => write!(f, "Hmac"),
+            Key::Rsa(_) => write!(f, "Rsa"),
+            Key::Ecdsa(_) => write!(f, "Ecdsa"),
+        }
+    }
+}
+
+fn read_der(path: &Path) -> anyhow::Result<Vec<u8>> {
+    let bytes = std::fs::read(path)?;
+    match std::str::from_utf8(&bytes) {
+        Ok(pem) if pem.trim_start().starts_with("-----BEGIN") => {
+            let body: String = pem
+                .lines()
+                .filter(|line| !line.starts_with("-----"))
+                .collect();
+            Ok(base64::decode(body.trim())?)
+        }
+        _ => Ok(bytes),
+    }
+}
+
+fn parse_algorithm(algorithm: Option<&str>) -> anyhow::Result<SignatureAlgorithm> {
+    match algorithm.unwrap_or("HS256") {
+        "HS256" => Ok(SignatureAlgorithm::HS256),
+        "RS256" => Ok(SignatureAlgorithm::RS256),
+        "ES256" => Ok(SignatureAlgorithm::ES256),
+        algorithm => Err(anyhow::anyhow!("unsupported JWT algorithm {}", algorithm)),
+    }
+}
+
+#[derive(Clone, Debug)]
+struct SigningKey {
+    id: String,
+    algorithm: SignatureAlgorithm,
+    key: Key,
+    retired_at: Option<DateTimeUtc>,
+}
+
+impl SigningKey {
+    /// Parses `<kid>:<algorithm>:<retired at, YYYY-MM-DD>:<secret or key path>`.
+    fn retired(spec: &str) -> anyhow::Result<Self> {
+        let mut parts = spec.splitn(4, ':');
+        let (id, algorithm, retired_at, value) =
+            match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                (Some(id), Some(algorithm), Some(retired_at), Some(value)) => {
+                    (id, algorithm, retired_at, value)
+                }
+                _ => return Err(anyhow::anyhow!("invalid retired JWT key {}", id_of(spec))),
+            };
+
+        let algorithm = parse_algorithm(Some(algorithm))?;
+        let retired_at =
+            chrono::NaiveDate::parse_from_str(retired_at, "%Y-%m-%d")?.and_hms(0, 0, 0);
+        let key = match algorithm {
+            SignatureAlgorithm::HS256 => Key::load(algorithm, value, None)?,
+            _ => Key::load(algorithm, "", Some(Path::new(value)))?,
+        };
+
+        Ok(Self {
+            id: id.to_owned(),
+            algorithm,
+            key,
+            retired_at: Some(DateTimeUtc::from_utc(retired_at, Utc)),
+        })
+    }
+
+    fn is_usable(&self) -> bool {
+        self.retired_at
+            .map_or(true, |retired_at| retired_at > Utc::now())
+    }
+}
+
+/// Never echo the secret part of a key specification back in errors.
+fn id_of(spec: &str) -> &str {
+    spec.split(':').next().unwrap_or_default()
+}
+
 #[derive(Clone, Debug)]
 pub struct Jwt {
-    secret: String,
+    active: SigningKey,
+    retired: Vec<SigningKey>,
+    issuer: Option<String>,
+    audience: Option<String>,
+    leeway: i64,
 }
 
 impl Jwt {
-    pub fn new(secret: &str) -> Self {
-        Self {
-            secret: secret.to_owned(),
-        }
+    pub fn new(args: &Args) -> anyhow::Result<Self> {
+        let Args {
+            jwt_secret,
+            jwt_algorithm,
+            jwt_private_key,
+            jwt_key_id,
+            jwt_retired_keys,
+            jwt_issuer,
+            jwt_audience,
+            jwt_leeway,
+            ..
+        } = args;
+        let algorithm = parse_algorithm(jwt_algorithm.as_deref())?;
+        let active = SigningKey {
+            id: jwt_key_id
+                .to_owned()
+                .unwrap_or_else(|| "default".to_owned()),
+            algorithm,
+            key: Key::load(algorithm, jwt_secret, jwt_private_key.as_deref())?,
+            retired_at: None,
+        };
+        let retired = jwt_retired_keys
+            .iter()
+            .map(|spec| SigningKey::retired(spec))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(Self {
+            active,
+            retired,
+            issuer: jwt_issuer.to_owned(),
+            audience: jwt_audience.to_owned(),
+            leeway: jwt_leeway.unwrap_or(60i64),
+        })
     }
 
-    pub fn encode(&self, claims: auth::Claims, _expiry: DateTimeUtc) -> anyhow::Result<String> {
-        let registered = biscuit::RegisteredClaims::default();
+    /// Tokens issued before key ids were introduced carry no `kid` and belong to the active key.
+    fn verification_key(&self, key_id: Option<&str>) -> anyhow::Result<&SigningKey> {
+        let key = match key_id {
+            None => Some(&self.active),
+            Some(id) if id == self.active.id => Some(&self.active),
+            Some(id) => self.retired.iter().find(|key| key.id == id),
+        };
+
+        key.filter(|key| key.is_usable())
+            .ok_or_else(|| auth::AuthError::InvalidCredentials.into())
+    }
+
+    pub fn encode(&self, claims: auth::Claims, expiry: DateTimeUtc) -> anyhow::Result<String> {
+        let now = Utc::now();
+        let registered = RegisteredClaims {
+            issuer: self.issuer.to_owned(),
+            audience: self.audience.to_owned().map(SingleOrMultiple::Single),
+            expiry: Some(expiry.into()),
+            not_before: Some(now.into()),
+            issued_at: Some(now.into()),
+            id: Some(claims.session()),
+            ..Default::default()
+        };
         let private = claims;
         let claims = biscuit::ClaimsSet::<auth::Claims> {
             registered,
@@ -23,24 +247,76 @@ impl Jwt {
 
         let jwt = biscuit::JWT::new_decoded(
             From::from(biscuit::jws::RegisteredHeader {
-                algorithm: biscuit::jwa::SignatureAlgorithm::HS256,
+                algorithm: self.active.algorithm,
+                key_id: Some(self.active.id.to_owned()),
                 ..Default::default()
             }),
             claims,
         );
 
-        let secret = biscuit::jws::Secret::bytes_from_str(&self.secret);
-
-        jwt.into_encoded(&secret)
+        jwt.into_encoded(&self.active.key.signing())
             .map(|t| t.unwrap_encoded().to_string())
             .map_err(|e| e.into())
     }
 
     pub fn decode(&self, token: &str) -> anyhow::Result<auth::Claims> {
         let token = biscuit::JWT::<auth::Claims, biscuit::Empty>::new_encoded(&token);
-        let secret = biscuit::jws::Secret::bytes_from_str(&self.secret);
-        let token = token.into_decoded(&secret, biscuit::jwa::SignatureAlgorithm::HS256)?;
+        let header = token.unverified_header()?;
+        let key = self.verification_key(header.registered.key_id.as_deref())?;
+        let token = token.into_decoded(&key.key.verifying(), key.algorithm)?;
+        token.validate(self.validation())?;
         let payload = token.payload()?.private.to_owned();
         Ok(payload)
     }
+
+    pub fn jwks(&self) -> serde_json::Value {
+        let keys = std::iter::once(&self.active)
+            .chain(self.retired.iter().filter(|key| key.is_usable()))
+            .filter_map(|key| {
+                key.key.jwk().map(|mut jwk| {
+                    jwk["kid"] = json!(key.id);
+                    jwk
+                })
+            })
+            .collect::<Vec<_>>();
+
+        json!({ "keys": keys })
+    }
+
+    fn validation(&self) -> ValidationOptions {
+        let presence = |configured: bool| {
+            if configured {
+                Presence::Required
+            } else {
+                Presence::Optional
+            }
+        };
+
+        ValidationOptions {
+            claim_presence_options: ClaimPresenceOptions {
+                issued_at: Presence::Required,
+                not_before: Presence::Required,
+                expiry: Presence::Required,
+                issuer: presence(self.issuer.is_some()),
+                audience: presence(self.audience.is_some()),
+                subject: Presence::Optional,
+                id: Presence::Required,
+            },
+            temporal_options: TemporalOptions {
+                epsilon: Duration::seconds(self.leeway),
+                now: None,
+            },
+            issuer: self
+                .issuer
+                .to_owned()
+                .map(Validation::Validate)
+                .unwrap_or(Validation::Ignored),
+            audience: self
+                .audience
+                .to_owned()
+                .map(Validation::Validate)
+                .unwrap_or(Validation::Ignored),
+            ..Default::default()
+        }
+    }
 }
@@ -0,0 +1,57 @@
+use crate::Args;
+use async_trait::async_trait;
+use std::{fmt, path::PathBuf, sync::Arc};
+use uuid::Uuid;
+
+#[derive(Debug)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[async_trait]
+pub trait Mailer: fmt::Debug + Send + Sync {
+    async fn send(&self, mail: Mail) -> anyhow::Result<()>;
+}
+
+pub fn new(args: &Args) -> Arc<dyn Mailer> {
+    match &args.mail_dir {
+        Some(dir) => Arc::new(FileMailer {
+            dir: dir.to_owned(),
+        }),
+        None => Arc::new(LogMailer),
+    }
+}
+
+/// Logs every mail instead of delivering it, meant for local development.
+#[derive(Debug)]
+pub struct LogMailer;
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, mail: Mail) -> anyhow::Result<()> {
+        tracing::info!(to = %mail.to, subject = %mail.subject, "mail:\n{}", mail.body);
+        Ok(())
+    }
+}
+
+/// Writes every mail to its own file in `dir`, so tests can pick them up.
+#[derive(Debug)]
+pub struct FileMailer {
+    dir: PathBuf,
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, mail: Mail) -> anyhow::Result<()> {
+        let Mail { to, subject, body } = mail;
+        let path = self.dir.join(format!("{}.eml", Uuid::new_v4()));
+        let contents = format!("To: {}\nSubject: {}\n\n{}\n", to, subject, body);
+
+        tokio::fs::create_dir_all(&self.dir).await?;
+        tokio::fs::write(path, contents).await?;
+
+        Ok(())
+    }
+}
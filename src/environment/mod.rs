@@ -1,10 +1,20 @@
 mod argon;
 mod jwt;
+mod mailer;
+pub mod oidc;
+mod totp;
+pub mod webauthn;
 
 use crate::Args;
 use argon::Argon;
 use jwt::Jwt;
+pub use mailer::Mail;
+use mailer::Mailer;
+use oidc::Oidc;
 use sqlx::postgres::PgPool;
+use std::sync::Arc;
+use totp::Totp;
+use webauthn::WebAuthn;
 
 #[derive(Clone, Debug)]
 pub struct Environment {
@@ -12,7 +22,25 @@ pub struct Environment {
     redis: redis::Client,
     argon: Argon,
     jwt: Jwt,
+    mailer: Arc<dyn Mailer>,
+    totp: Totp,
+    webauthn: WebAuthn,
+    oidc: Oidc,
     session_lifetime: Option<i64>,
+    refresh_token_lifetime: Option<i64>,
+    password_reset_lifetime: Option<i64>,
+    mail_interval: Option<usize>,
+    email_verification_lifetime: Option<i64>,
+    magic_link_lifetime: Option<i64>,
+    magic_link_url: String,
+    invitation_lifetime: Option<i64>,
+    invitation_url: String,
+    require_verified_email: bool,
+    login_rate_limit: Option<u64>,
+    login_rate_window: Option<u64>,
+    lockout_threshold: Option<i32>,
+    lockout_duration: Option<i64>,
+    oauth_access_token_lifetime: Option<i64>,
 }
 
 impl Environment {
@@ -21,19 +49,58 @@ impl Environment {
             database_url,
             redis_url,
             session_lifetime,
-            jwt_secret,
+            refresh_token_lifetime,
+            password_reset_lifetime,
+            mail_interval,
+            email_verification_lifetime,
+            magic_link_lifetime,
+            magic_link_url,
+            invitation_lifetime,
+            invitation_url,
+            require_verified_email,
+            login_rate_limit,
+            login_rate_window,
+            lockout_threshold,
+            lockout_duration,
+            oauth_access_token_lifetime,
             ..
         } = &args;
         let db_pool = PgPool::builder().max_size(5).build(database_url).await?;
         let redis = redis::Client::open(redis_url.as_str())?;
         let argon = Argon::new(&args);
-        let jwt = Jwt::new(&jwt_secret);
+        let jwt = Jwt::new(&args)?;
+        let mailer = mailer::new(&args);
+        let totp = Totp::new(&args);
+        let webauthn = WebAuthn::new(&args);
+        let oidc = Oidc::new(&args)?;
         Ok(Self {
             db_pool,
             redis,
             argon,
             jwt,
+            mailer,
+            totp,
+            webauthn,
+            oidc,
             session_lifetime: session_lifetime.to_owned(),
+            refresh_token_lifetime: refresh_token_lifetime.to_owned(),
+            password_reset_lifetime: password_reset_lifetime.to_owned(),
+            mail_interval: mail_interval.to_owned(),
+            email_verification_lifetime: email_verification_lifetime.to_owned(),
+            magic_link_lifetime: magic_link_lifetime.to_owned(),
+            magic_link_url: magic_link_url
+                .to_owned()
+                .unwrap_or_else(|| format!("http://{}/auth/magic", args.host)),
+            invitation_lifetime: invitation_lifetime.to_owned(),
+            invitation_url: invitation_url
+                .to_owned()
+                .unwrap_or_else(|| format!("http://{}/invitations", args.host)),
+            require_verified_email: require_verified_email.unwrap_or(false),
+            login_rate_limit: login_rate_limit.to_owned(),
+            login_rate_window: login_rate_window.to_owned(),
+            lockout_threshold: lockout_threshold.to_owned(),
+            lockout_duration: lockout_duration.to_owned(),
+            oauth_access_token_lifetime: oauth_access_token_lifetime.to_owned(),
         })
     }
 
@@ -56,7 +123,79 @@ impl Environment {
         &self.jwt
     }
 
+    pub fn mailer(&self) -> &dyn Mailer {
+        self.mailer.as_ref()
+    }
+
+    pub fn totp(&self) -> &Totp {
+        &self.totp
+    }
+
+    pub fn webauthn(&self) -> &WebAuthn {
+        &self.webauthn
+    }
+
+    pub fn oidc(&self) -> &Oidc {
+        &self.oidc
+    }
+
     pub fn session_lifetime(&self, req_lifetime: Option<i64>) -> i64 {
         req_lifetime.or(self.session_lifetime).unwrap_or(86400i64)
     }
+
+    pub fn refresh_token_lifetime(&self) -> i64 {
+        self.refresh_token_lifetime.unwrap_or(2592000i64)
+    }
+
+    pub fn password_reset_lifetime(&self) -> i64 {
+        self.password_reset_lifetime.unwrap_or(3600i64)
+    }
+
+    pub fn mail_interval(&self) -> usize {
+        self.mail_interval.unwrap_or(60usize)
+    }
+
+    pub fn email_verification_lifetime(&self) -> i64 {
+        self.email_verification_lifetime.unwrap_or(86400i64)
+    }
+
+    pub fn magic_link_lifetime(&self) -> i64 {
+        self.magic_link_lifetime.unwrap_or(900i64)
+    }
+
+    pub fn magic_link_url(&self) -> &str {
+        &self.magic_link_url
+    }
+
+    pub fn invitation_lifetime(&self) -> i64 {
+        self.invitation_lifetime.unwrap_or(604800i64)
+    }
+
+    pub fn invitation_url(&self) -> &str {
+        &self.invitation_url
+    }
+
+    pub fn require_verified_email(&self) -> bool {
+        self.require_verified_email
+    }
+
+    /// Maximum login attempts and the window in seconds they are counted over.
+    pub fn login_rate_limit(&self) -> (u64, u64) {
+        (
+            self.login_rate_limit.unwrap_or(10u64),
+            self.login_rate_window.unwrap_or(60u64),
+        )
+    }
+
+    pub fn lockout_threshold(&self) -> i32 {
+        self.lockout_threshold.unwrap_or(5i32)
+    }
+
+    pub fn lockout_duration(&self) -> i64 {
+        self.lockout_duration.unwrap_or(900i64)
+    }
+
+    pub fn oauth_access_token_lifetime(&self) -> i64 {
+        self.oauth_access_token_lifetime.unwrap_or(3600i64)
+    }
 }
@@ -0,0 +1,92 @@
+use crate::Args;
+use std::{collections::HashMap, fmt};
+
+/// An OpenID Connect provider, its endpoints are discovered from the issuer.
+#[derive(Clone)]
+pub struct Provider {
+    pub name: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub issuer: String,
+    /// Whether a verified email address of this provider logs into an existing account
+    pub trusted: bool,
+}
+
+impl fmt::Debug for Provider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Provider")
+            .field("name", &self.name)
+            .field("client_id", &self.client_id)
+            .field("issuer", &self.issuer)
+            .field("trusted", &self.trusted)
+            .finish()
+    }
+}
+
+impl Provider {
+    /// Parses `<name>:<client id>:<client secret>:<issuer url>`.
+    fn parse(spec: &str) -> anyhow::Result<Self> {
+        let mut parts = spec.splitn(4, ':');
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(name), Some(client_id), Some(client_secret), Some(issuer)) => Ok(Self {
+                name: name.to_owned(),
+                client_id: client_id.to_owned(),
+                client_secret: client_secret.to_owned(),
+                issuer: issuer.to_owned(),
+                trusted: false,
+            }),
+            _ => Err(anyhow::anyhow!(
+                "invalid OIDC provider {}",
+                spec.split(':').next().unwrap_or_default()
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Oidc {
+    providers: HashMap<String, Provider>,
+    redirect_url: String,
+    client: reqwest::Client,
+}
+
+impl Oidc {
+    pub fn new(args: &Args) -> anyhow::Result<Self> {
+        let Args {
+            oidc_providers,
+            oidc_redirect_url,
+            oidc_trusted_providers,
+            host,
+            ..
+        } = args;
+        let providers = oidc_providers
+            .iter()
+            .map(|spec| {
+                let mut provider = Provider::parse(spec)?;
+                provider.trusted = oidc_trusted_providers.contains(&provider.name);
+                Ok((provider.name.to_owned(), provider))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            providers,
+            redirect_url: oidc_redirect_url
+                .to_owned()
+                .unwrap_or_else(|| format!("http://{}", host))
+                .trim_end_matches('/')
+                .to_owned(),
+            client: reqwest::Client::new(),
+        })
+    }
+
+    pub fn provider(&self, name: &str) -> Option<&Provider> {
+        self.providers.get(name)
+    }
+
+    pub fn redirect_uri(&self, provider: &Provider) -> String {
+        format!("{}/auth/oidc/{}/callback", self.redirect_url, provider.name)
+    }
+
+    pub fn client(&self) -> &reqwest::Client {
+        &self.client
+    }
+}
@@ -0,0 +1,312 @@
+use crate::{auth::AuthError, Args};
+use rand::Rng;
+use ring::{digest, signature};
+use serde::Deserialize;
+use serde_cbor::Value;
+
+const USER_PRESENT: u8 = 0x01;
+const USER_VERIFIED: u8 = 0x04;
+const ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+/// COSE algorithm identifiers of ES256 and RS256, the two every platform authenticator supports.
+pub const ALGORITHMS: [i64; 2] = [-7, -257];
+
+/// Relying party of the WebAuthn ceremonies. Registration asks for `none` attestation, so
+/// attestation statements are not verified, only the credential public key is kept.
+#[derive(Clone, Debug)]
+pub struct WebAuthn {
+    rp_id: String,
+    rp_name: String,
+    origin: String,
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+}
+
+pub struct Credential {
+    pub id: String,
+    pub public_key: Vec<u8>,
+    pub sign_count: u32,
+}
+
+/// What the authenticator vouched for in an assertion.
+pub struct Verification {
+    pub sign_count: u32,
+    /// Set when the user was verified with a PIN or biometrics, not merely present
+    pub user_verified: bool,
+}
+
+enum PublicKey {
+    Ec2(Vec<u8>),
+    Rsa { n: Vec<u8>, e: Vec<u8> },
+}
+
+impl WebAuthn {
+    pub fn new(args: &Args) -> Self {
+        let Args {
+            webauthn_rp_id,
+            webauthn_rp_name,
+            webauthn_origin,
+            ..
+        } = args;
+        let rp_id = webauthn_rp_id
+            .to_owned()
+            .unwrap_or_else(|| "localhost".to_owned());
+        Self {
+            origin: webauthn_origin
+                .to_owned()
+                .unwrap_or_else(|| format!("https://{}", rp_id)),
+            rp_name: webauthn_rp_name
+                .to_owned()
+                .unwrap_or_else(|| "warp-api-app".to_owned()),
+            rp_id,
+        }
+    }
+
+    pub fn rp_id(&self) -> &str {
+        &self.rp_id
+    }
+
+    pub fn rp_name(&self) -> &str {
+        &self.rp_name
+    }
+
+    pub fn generate_challenge(&self) -> String {
+        let mut challenge = [0u8; 32];
+        rand::thread_rng().fill(&mut challenge[..]);
+        encode(&challenge)
+    }
+
+    /// Checks the ceremony type and origin of the client data and returns its challenge.
+    pub fn challenge(&self, client_data: &[u8], kind: &str) -> Result<String, AuthError> {
+        let client_data: ClientData =
+            serde_json::from_slice(client_data).or(Err(AuthError::InvalidCredentials))?;
+
+        if client_data.kind != kind || client_data.origin != self.origin {
+            return Err(AuthError::InvalidCredentials);
+        }
+
+        Ok(client_data.challenge)
+    }
+
+    /// Extracts the new credential from an attestation object.
+    pub fn register(&self, attestation_object: &[u8]) -> Result<Credential, AuthError> {
+        let authenticator_data = match serde_cbor::from_slice(attestation_object) {
+            Ok(Value::Map(map)) => match map.get(&Value::Text("authData".to_owned())) {
+                Some(Value::Bytes(data)) => data.to_owned(),
+                _ => return Err(AuthError::InvalidCredentials),
+            },
+            _ => return Err(AuthError::InvalidCredentials),
+        };
+
+        let (flags, sign_count) = self.authenticator_data(&authenticator_data)?;
+        if flags & ATTESTED_CREDENTIAL_DATA == 0 || authenticator_data.len() < 55 {
+            return Err(AuthError::InvalidCredentials);
+        }
+
+        // 16 bytes of AAGUID precede the length of the credential id.
+        let length = u16::from_be_bytes([authenticator_data[53], authenticator_data[54]]) as usize;
+        let rest = authenticator_data
+            .get(55..)
+            .filter(|rest| rest.len() > length)
+            .ok_or(AuthError::InvalidCredentials)?;
+        let (id, rest) = rest.split_at(length);
+
+        // Extensions may follow the key, so only the first CBOR item is taken.
+        let mut deserializer = serde_cbor::Deserializer::from_slice(rest);
+        serde::Deserialize::deserialize(&mut deserializer)
+            .map(|_: Value| ())
+            .or(Err(AuthError::InvalidCredentials))?;
+        let public_key = rest[..deserializer.byte_offset()].to_vec();
+        parse_public_key(&public_key)?;
+
+        Ok(Credential {
+            id: encode(id),
+            public_key,
+            sign_count,
+        })
+    }
+
+    /// Verifies an assertion signature with a stored COSE public key.
+    pub fn verify(
+        &self,
+        public_key: &[u8],
+        authenticator_data: &[u8],
+        client_data: &[u8],
+        signature: &[u8],
+    ) -> Result<Verification, AuthError> {
+        let (flags, sign_count) = self.authenticator_data(authenticator_data)?;
+
+        let mut message = authenticator_data.to_vec();
+        message.extend_from_slice(digest::digest(&digest::SHA256, client_data).as_ref());
+
+        match parse_public_key(public_key)? {
+            PublicKey::Ec2(point) => {
+                signature::UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_ASN1, point)
+                    .verify(&message, signature)
+            }
+            PublicKey::Rsa { n, e } => signature::RsaPublicKeyComponents { n, e }.verify(
+                &signature::RSA_PKCS1_2048_8192_SHA256,
+                &message,
+                signature,
+            ),
+        }
+        .or(Err(AuthError::InvalidCredentials))?;
+
+        Ok(Verification {
+            sign_count,
+            user_verified: flags & USER_VERIFIED != 0,
+        })
+    }
+
+    fn authenticator_data(&self, data: &[u8]) -> Result<(u8, u32), AuthError> {
+        if data.len() < 37 {
+            return Err(AuthError::InvalidCredentials);
+        }
+
+        let rp_id_hash = digest::digest(&digest::SHA256, self.rp_id.as_bytes());
+        if &data[..32] != rp_id_hash.as_ref() || data[32] & USER_PRESENT == 0 {
+            return Err(AuthError::InvalidCredentials);
+        }
+
+        Ok((
+            data[32],
+            u32::from_be_bytes([data[33], data[34], data[35], data[36]]),
+        ))
+    }
+}
+
+/// Base64url without padding, the encoding WebAuthn uses for binary values in JSON.
+pub fn encode(value: &[u8]) -> String {
+    base64::encode_config(value, base64::URL_SAFE_NO_PAD)
+}
+
+pub fn decode(value: &str) -> Result<Vec<u8>, AuthError> {
+    base64::decode_config(value.trim_end_matches('='), base64::URL_SAFE_NO_PAD)
+        .or(Err(AuthError::InvalidCredentials))
+}
+
+fn parse_public_key(cose: &[u8]) -> Result<PublicKey, AuthError> {
+    let map = match serde_cbor::from_slice(cose) {
+        Ok(Value::Map(map)) => map,
+        _ => return Err(AuthError::InvalidCredentials),
+    };
+    let integer = |key: i128| match map.get(&Value::Integer(key)) {
+        Some(Value::Integer(value)) => Some(*value),
+        _ => None,
+    };
+    let bytes = |key: i128| match map.get(&Value::Integer(key)) {
+        Some(Value::Bytes(value)) => Some(value.to_owned()),
+        _ => None,
+    };
+
+    // Key type 2 is EC2 on curve 1 (P-256), key type 3 is RSA.
+    match (integer(1), integer(3)) {
+        (Some(2), Some(-7)) if integer(-1) == Some(1) => match (bytes(-2), bytes(-3)) {
+            (Some(x), Some(y)) if x.len() == 32 && y.len() == 32 => {
+                let mut point = vec![0x04];
+                point.extend(x);
+                point.extend(y);
+                Ok(PublicKey::Ec2(point))
+            }
+            _ => Err(AuthError::InvalidCredentials),
+        },
+        (Some(3), Some(-257)) => match (bytes(-1), bytes(-2)) {
+            (Some(n), Some(e)) => Ok(PublicKey::Rsa { n, e }),
+            _ => Err(AuthError::InvalidCredentials),
+        },
+        _ => Err(AuthError::InvalidCredentials),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::{
+        rand::SystemRandom,
+        signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING},
+    };
+    use std::collections::BTreeMap;
+
+    const CLIENT_DATA: &[u8] = br#"{"type":"webauthn.get"}"#;
+
+    fn webauthn() -> WebAuthn {
+        WebAuthn {
+            rp_id: "localhost".to_owned(),
+            rp_name: "test".to_owned(),
+            origin: "https://localhost".to_owned(),
+        }
+    }
+
+    /// Signs an assertion with a fresh P-256 key, returning its COSE public key, the
+    /// authenticator data and the signature.
+    fn assertion(flags: u8) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+        let key =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref()).unwrap();
+
+        let point = key.public_key().as_ref();
+        let mut cose = BTreeMap::new();
+        cose.insert(Value::Integer(1), Value::Integer(2));
+        cose.insert(Value::Integer(3), Value::Integer(-7));
+        cose.insert(Value::Integer(-1), Value::Integer(1));
+        cose.insert(Value::Integer(-2), Value::Bytes(point[1..33].to_vec()));
+        cose.insert(Value::Integer(-3), Value::Bytes(point[33..65].to_vec()));
+        let public_key = serde_cbor::to_vec(&Value::Map(cose)).unwrap();
+
+        let mut authenticator_data = digest::digest(&digest::SHA256, b"localhost")
+            .as_ref()
+            .to_vec();
+        authenticator_data.push(flags);
+        authenticator_data.extend_from_slice(&7u32.to_be_bytes());
+
+        let mut message = authenticator_data.clone();
+        message.extend_from_slice(digest::digest(&digest::SHA256, CLIENT_DATA).as_ref());
+        let signature = key.sign(&rng, &message).unwrap().as_ref().to_vec();
+
+        (public_key, authenticator_data, signature)
+    }
+
+    #[test]
+    fn reports_user_verification() {
+        let (public_key, data, signature) = assertion(USER_PRESENT | USER_VERIFIED);
+        let verification = webauthn()
+            .verify(&public_key, &data, CLIENT_DATA, &signature)
+            .unwrap();
+
+        assert!(verification.user_verified);
+        assert_eq!(verification.sign_count, 7);
+    }
+
+    #[test]
+    fn touch_alone_is_not_user_verification() {
+        let (public_key, data, signature) = assertion(USER_PRESENT);
+        let verification = webauthn()
+            .verify(&public_key, &data, CLIENT_DATA, &signature)
+            .unwrap();
+
+        assert!(!verification.user_verified);
+    }
+
+    #[test]
+    fn rejects_assertions_without_user_presence() {
+        let (public_key, data, signature) = assertion(USER_VERIFIED);
+        assert!(webauthn()
+            .verify(&public_key, &data, CLIENT_DATA, &signature)
+            .is_err());
+    }
+
+    #[test]
+    fn rejects_altered_flags() {
+        let (public_key, mut data, signature) = assertion(USER_PRESENT);
+        data[32] |= USER_VERIFIED;
+        assert!(webauthn()
+            .verify(&public_key, &data, CLIENT_DATA, &signature)
+            .is_err());
+    }
+}
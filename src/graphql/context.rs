@@ -1,4 +1,11 @@
-use crate::{environment::Environment, session::Session};
+use crate::{
+    api_key,
+    auth::{AuthError, Credentials},
+    environment::Environment,
+    helpers::rate_limit,
+    session::{Organization, Session},
+    sql::tenant::{self, Tenant, TenantTransaction},
+};
 use shrinkwraprs::Shrinkwrap;
 
 #[derive(Shrinkwrap, Clone)]
@@ -9,12 +16,21 @@ pub struct Context {
 }
 
 impl Context {
-    pub async fn new(env: Environment, auth: Option<(String, String)>) -> anyhow::Result<Self> {
-        if let Some((jwt, csrf)) = auth {
-            let session = Some(Session::new(env.clone(), &jwt, &csrf).await?);
-            Ok(Self { env, session })
-        } else {
-            Ok(Self { env, session: None })
+    pub async fn new(env: Environment, auth: Option<Credentials>) -> anyhow::Result<Self> {
+        match auth {
+            Some(Credentials::Session { jwt, csrf }) => {
+                let session = Some(Session::new(env.clone(), &jwt, &csrf).await?);
+                Ok(Self { env, session })
+            }
+            Some(Credentials::Bearer(token)) if token.starts_with(api_key::KEY_PREFIX) => {
+                let session = Some(Session::from_api_key(env.clone(), &token).await?);
+                Ok(Self { env, session })
+            }
+            Some(Credentials::Bearer(token)) => {
+                let session = Some(Session::bearer(env.clone(), &token).await?);
+                Ok(Self { env, session })
+            }
+            None => Ok(Self { env, session: None }),
         }
     }
 
@@ -22,8 +38,76 @@ impl Context {
         self.session.as_ref()
     }
 
-    pub fn is_authenticated(&self) -> bool {
-        self.session.is_some()
+    /// Opens a transaction scoped to the session, in which row-level security hides the rows of
+    /// other accounts and organizations even from a resolver that forgets to filter them.
+    pub async fn transaction(&self) -> anyhow::Result<TenantTransaction> {
+        let session = self.session().ok_or(AuthError::InvalidCredentials)?;
+
+        tenant::begin(
+            self.database(),
+            &Tenant {
+                account: session.account_id(),
+                organization: session.organization().map(|organization| organization.id),
+                admin: session.has_permission("accounts:read"),
+            },
+        )
+        .await
+    }
+
+    /// Guards resolvers to accounts granted `permission` by one of their roles, yielding their
+    /// session.
+    pub fn require_permission(&self, permission: &str) -> Result<&Session, AuthError> {
+        let session = self.session().ok_or(AuthError::InvalidCredentials)?;
+        if !session.has_permission(permission) {
+            return Err(AuthError::Forbidden);
+        }
+
+        Ok(session)
+    }
+
+    /// What the request is accounted to by the rate limit, anonymous requests only count against
+    /// their address.
+    pub fn rate_limit_key(&self) -> Option<rate_limit::Key> {
+        self.session().map(|session| match session.api_key_id() {
+            Some(id) => rate_limit::Key::ApiKey(id.to_owned()),
+            None => rate_limit::Key::Account(session.account_id()),
+        })
+    }
+
+    /// Guards resolvers to the active organization of the session, when the account holds one of
+    /// `roles` there.
+    pub fn require_organization(
+        &self,
+        roles: &[&str],
+    ) -> Result<(&Session, &Organization), AuthError> {
+        let session = self.session().ok_or(AuthError::InvalidCredentials)?;
+        let organization = session.organization().ok_or(AuthError::NoOrganization)?;
+        if !roles.contains(&organization.role.as_str()) {
+            return Err(AuthError::Forbidden);
+        }
+
+        Ok((session, organization))
+    }
+
+    pub fn require_scope(&self, scope: &str) -> Result<(), AuthError> {
+        match &self.session {
+            Some(session) if !session.has_scope(scope) => Err(AuthError::InsufficientScope),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn require_not_impersonating(&self) -> Result<(), AuthError> {
+        match &self.session {
+            Some(session) => session.require_not_impersonating(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn require_first_party(&self) -> Result<(), AuthError> {
+        match &self.session {
+            Some(session) => session.require_first_party(),
+            None => Ok(()),
+        }
     }
 }
 
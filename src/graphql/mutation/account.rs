@@ -1,15 +1,10 @@
-use crate::graphql::{mutation::Mutation, Context};
+use crate::audit::{self, Event};
+use crate::graphql::Context;
 use crate::{auth, model};
+use chrono::{DateTime, Utc};
 use juniper::FieldResult;
 use uuid::Uuid;
 
-#[juniper::graphql_object(Context = Context)]
-impl Mutation {
-    fn account() -> AccountMutation {
-        AccountMutation
-    }
-}
-
 #[derive(juniper::GraphQLInputObject, Debug)]
 pub struct CreateAccountInput {
     email: String,
@@ -23,6 +18,21 @@ pub struct AccountInput {
     email: String,
 }
 
+#[derive(juniper::GraphQLInputObject, Debug)]
+pub struct ApiKeyInput {
+    name: String,
+    /// Space separated scopes of the key, of `read` and `write`
+    scope: String,
+    expiry: Option<DateTime<Utc>>,
+}
+
+#[derive(juniper::GraphQLObject, Debug)]
+pub struct CreatedApiKey {
+    api_key: model::ApiKey,
+    /// Only returned once, send it as `Authorization: Bearer <key>`
+    key: String,
+}
+
 #[juniper::graphql_object(Context = Context)]
 impl AccountMutation {
     async fn create(ctx: &Context, input: CreateAccountInput) -> FieldResult<model::Account> {
@@ -33,20 +43,131 @@ impl AccountMutation {
         let id = Uuid::new_v4();
 
         crate::sql::account::create_account(ctx.database(), id, &email, &password).await?;
+        audit::record(
+            ctx,
+            Event::AccountCreated,
+            audit::Entry {
+                account: Some(id),
+                actor: ctx.session().map(|session| session.actor()),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        if let Err(err) = crate::verification::send(ctx, id, &email).await {
+            tracing::error!("could not send verification mail: {:#}", err);
+        }
 
         Ok(crate::sql::account::get_account(ctx.database(), &email).await?)
     }
 
     async fn update(ctx: &Context, id: Uuid, input: AccountInput) -> FieldResult<model::Account> {
-        let acc = ctx
-            .session()
-            .ok_or(auth::AuthError::InvalidCredentials)?
-            .account()
-            .await?;
-        if acc.id != id {
-            return Err(auth::AuthError::InvalidCredentials.into());
+        ctx.require_first_party()?;
+        ctx.require_not_impersonating()?;
+        let session = ctx.session().ok_or(auth::AuthError::InvalidCredentials)?;
+        if session.account_id() != id {
+            ctx.require_permission("accounts:write")?;
+        }
+        let acc = crate::sql::account::get_account_by_id(ctx.database(), id).await?;
+
+        let updated = crate::sql::account::update_email(ctx.database(), id, &input.email).await?;
+
+        if updated.email != acc.email {
+            let detail = format!("email changed from {} to {}", acc.email, updated.email);
+            audit::record(
+                ctx,
+                Event::AccountUpdated,
+                audit::Entry {
+                    account: Some(id),
+                    actor: Some(session.actor()),
+                    detail: Some(&detail),
+                    ..Default::default()
+                },
+            )
+            .await;
+
+            if let Err(err) = crate::verification::send(ctx, id, &updated.email).await {
+                tracing::error!("could not send verification mail: {:#}", err);
+            }
         }
 
-        Ok(crate::sql::account::update_email(ctx.database(), id, &input.email).await?)
+        Ok(updated)
+    }
+
+    async fn verify_email(ctx: &Context, token: String) -> FieldResult<bool> {
+        crate::verification::verify(ctx, &token).await?;
+
+        Ok(true)
+    }
+
+    async fn resend_verification(ctx: &Context, email: String) -> FieldResult<bool> {
+        crate::verification::resend(ctx, &email).await?;
+
+        Ok(true)
+    }
+
+    async fn change_password(
+        ctx: &Context,
+        current_password: String,
+        new_password: String,
+        revoke_other_sessions: Option<bool>,
+    ) -> FieldResult<bool> {
+        ctx.require_first_party()?;
+        ctx.require_not_impersonating()?;
+        let session = ctx.session().ok_or(auth::AuthError::InvalidCredentials)?;
+
+        crate::password::change(
+            ctx,
+            session,
+            &current_password,
+            &new_password,
+            revoke_other_sessions.unwrap_or(false),
+        )
+        .await?;
+
+        Ok(true)
+    }
+
+    /// Mails a single-use login link, whether or not the address belongs to an account
+    async fn request_magic_link(ctx: &Context, email: String) -> FieldResult<bool> {
+        crate::magic_link::request(ctx, &email).await?;
+
+        Ok(true)
+    }
+
+    async fn request_password_reset(ctx: &Context, email: String) -> FieldResult<bool> {
+        crate::password::request_reset(ctx, &email).await?;
+
+        Ok(true)
+    }
+
+    async fn reset_password(
+        ctx: &Context,
+        token: String,
+        new_password: String,
+    ) -> FieldResult<bool> {
+        crate::password::reset(ctx, &token, &new_password).await?;
+
+        Ok(true)
+    }
+
+    async fn create_api_key(ctx: &Context, input: ApiKeyInput) -> FieldResult<CreatedApiKey> {
+        ctx.require_first_party()?;
+        ctx.require_not_impersonating()?;
+        let session = ctx.session().ok_or(auth::AuthError::InvalidCredentials)?;
+        let created =
+            crate::api_key::create(ctx, session, &input.name, &input.scope, input.expiry).await?;
+
+        Ok(CreatedApiKey {
+            api_key: created.api_key,
+            key: created.key,
+        })
+    }
+
+    async fn revoke_api_key(ctx: &Context, id: Uuid) -> FieldResult<bool> {
+        ctx.require_first_party()?;
+        let session = ctx.session().ok_or(auth::AuthError::InvalidCredentials)?;
+
+        Ok(crate::api_key::revoke(ctx, session, id).await?)
     }
 }
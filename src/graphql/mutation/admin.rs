@@ -0,0 +1,59 @@
+use crate::graphql::Context;
+use crate::{auth, model};
+use chrono::{DateTime, Utc};
+use juniper::FieldResult;
+use uuid::Uuid;
+
+/// Credentials of a session acting as another account
+#[derive(juniper::GraphQLObject, Debug)]
+pub struct Impersonation {
+    jwt: String,
+    csrf: String,
+    expiry: DateTime<Utc>,
+}
+
+pub struct AdminMutation;
+
+#[juniper::graphql_object(Context = Context)]
+impl AdminMutation {
+    /// Blocks logins and revokes every session of the account
+    async fn disable_account(ctx: &Context, id: Uuid) -> FieldResult<model::Account> {
+        let session = ctx.session().ok_or(auth::AuthError::InvalidCredentials)?;
+
+        Ok(crate::admin::disable(ctx, session, id).await?)
+    }
+
+    async fn enable_account(ctx: &Context, id: Uuid) -> FieldResult<model::Account> {
+        let session = ctx.session().ok_or(auth::AuthError::InvalidCredentials)?;
+
+        Ok(crate::admin::enable(ctx, session, id).await?)
+    }
+
+    /// Invalidates the password, revokes every session and mails a reset token to the account
+    async fn force_password_reset(ctx: &Context, id: Uuid) -> FieldResult<bool> {
+        let session = ctx.session().ok_or(auth::AuthError::InvalidCredentials)?;
+        crate::admin::force_password_reset(ctx, session, id).await?;
+
+        Ok(true)
+    }
+
+    /// Opens a session of the account, flagged with the staff member as impersonator
+    async fn impersonate(ctx: &Context, account_id: Uuid) -> FieldResult<Impersonation> {
+        ctx.require_not_impersonating()?;
+        let session = ctx.session().ok_or(auth::AuthError::InvalidCredentials)?;
+        let impersonation = crate::impersonation::start(ctx, session, account_id).await?;
+
+        Ok(Impersonation {
+            jwt: impersonation.jwt,
+            csrf: impersonation.csrf,
+            expiry: impersonation.expiry,
+        })
+    }
+
+    /// Returns the number of revoked sessions
+    async fn revoke_sessions(ctx: &Context, id: Uuid) -> FieldResult<i32> {
+        let session = ctx.session().ok_or(auth::AuthError::InvalidCredentials)?;
+
+        Ok(crate::admin::revoke_sessions(ctx, session, id).await? as i32)
+    }
+}
@@ -1,3 +1,54 @@
 mod account;
+mod admin;
+mod oauth;
+mod organization;
+mod session;
+mod two_factor;
+
+use crate::graphql::Context;
+use account::AccountMutation;
+use admin::AdminMutation;
+use juniper::FieldResult;
+use oauth::OauthMutation;
+use organization::OrganizationMutation;
+use session::SessionMutation;
+use two_factor::TwoFactorMutation;
 
 pub struct Mutation;
+
+#[juniper::graphql_object(Context = Context)]
+impl Mutation {
+    fn account(ctx: &Context) -> FieldResult<AccountMutation> {
+        ctx.require_scope("write")?;
+        Ok(AccountMutation)
+    }
+
+    /// Account lifecycle management for staff
+    fn admin(ctx: &Context) -> FieldResult<AdminMutation> {
+        ctx.require_first_party()?;
+        ctx.require_permission("accounts:write")?;
+        Ok(AdminMutation)
+    }
+
+    fn session(ctx: &Context) -> FieldResult<SessionMutation> {
+        ctx.require_scope("write")?;
+        Ok(SessionMutation)
+    }
+
+    fn two_factor(ctx: &Context) -> FieldResult<TwoFactorMutation> {
+        ctx.require_first_party()?;
+        ctx.require_not_impersonating()?;
+        Ok(TwoFactorMutation)
+    }
+
+    fn oauth(ctx: &Context) -> FieldResult<OauthMutation> {
+        ctx.require_first_party()?;
+        ctx.require_not_impersonating()?;
+        Ok(OauthMutation)
+    }
+
+    fn organization(ctx: &Context) -> FieldResult<OrganizationMutation> {
+        ctx.require_first_party()?;
+        Ok(OrganizationMutation)
+    }
+}
@@ -0,0 +1,53 @@
+use crate::auth;
+use crate::graphql::Context;
+use juniper::FieldResult;
+
+#[derive(juniper::GraphQLInputObject, Debug)]
+pub struct OauthClientInput {
+    name: String,
+    redirect_uri: String,
+    /// Space separated scopes the client may request, of `read` and `write`
+    scope: String,
+    /// Confidential clients get a secret and may use the client credentials grant
+    confidential: bool,
+}
+
+#[derive(juniper::GraphQLObject, Debug)]
+pub struct OauthClientRegistration {
+    client_id: String,
+    /// Only returned once
+    client_secret: Option<String>,
+}
+
+pub struct OauthMutation;
+
+#[juniper::graphql_object(Context = Context)]
+impl OauthMutation {
+    async fn register_client(
+        ctx: &Context,
+        input: OauthClientInput,
+    ) -> FieldResult<OauthClientRegistration> {
+        let session = ctx.session().ok_or(auth::AuthError::InvalidCredentials)?;
+        let registration = crate::oauth::register(
+            ctx,
+            session,
+            &input.name,
+            &input.redirect_uri,
+            &input.scope,
+            input.confidential,
+        )
+        .await?;
+
+        Ok(OauthClientRegistration {
+            client_id: registration.client_id,
+            client_secret: registration.client_secret,
+        })
+    }
+
+    /// Deletes the client and revokes every token issued to it
+    async fn delete_client(ctx: &Context, client_id: String) -> FieldResult<bool> {
+        let session = ctx.session().ok_or(auth::AuthError::InvalidCredentials)?;
+
+        Ok(crate::oauth::delete(ctx, session, &client_id).await?)
+    }
+}
@@ -0,0 +1,68 @@
+use crate::graphql::Context;
+use crate::organization::{MANAGERS, ROLES};
+use crate::{auth, model};
+use juniper::FieldResult;
+use uuid::Uuid;
+
+#[derive(juniper::GraphQLInputObject, Debug)]
+pub struct InvitationInput {
+    email: String,
+    /// One of `owner`, `admin` or `member`
+    role: String,
+}
+
+pub struct OrganizationMutation;
+
+#[juniper::graphql_object(Context = Context)]
+impl OrganizationMutation {
+    /// Creates an organization owned by the account and switches to it
+    async fn create(ctx: &Context, name: String) -> FieldResult<model::Organization> {
+        let session = ctx.session().ok_or(auth::AuthError::InvalidCredentials)?;
+
+        Ok(crate::organization::create(ctx, session, &name).await?)
+    }
+
+    /// Switches the session to another organization, or to none when no id is given
+    async fn switch(ctx: &Context, id: Option<Uuid>) -> FieldResult<Option<model::Organization>> {
+        let session = ctx.session().ok_or(auth::AuthError::InvalidCredentials)?;
+
+        Ok(crate::organization::switch(ctx, session, id).await?)
+    }
+
+    /// Mails an invitation to join the active organization
+    async fn invite(ctx: &Context, input: InvitationInput) -> FieldResult<bool> {
+        let (session, organization) = ctx.require_organization(&MANAGERS)?;
+        crate::organization::invite(ctx, session, organization, &input.email, &input.role).await?;
+
+        Ok(true)
+    }
+
+    async fn revoke_invitation(ctx: &Context, id: Uuid) -> FieldResult<bool> {
+        let (_, organization) = ctx.require_organization(&MANAGERS)?;
+
+        Ok(crate::organization::revoke_invitation(ctx, organization, id).await?)
+    }
+
+    async fn accept_invitation(ctx: &Context, token: String) -> FieldResult<model::Organization> {
+        let session = ctx.session().ok_or(auth::AuthError::InvalidCredentials)?;
+
+        Ok(crate::organization::accept(ctx, session, &token).await?)
+    }
+
+    async fn decline_invitation(ctx: &Context, token: String) -> FieldResult<bool> {
+        Ok(crate::organization::decline(ctx, &token).await?)
+    }
+
+    async fn change_member_role(ctx: &Context, account: Uuid, role: String) -> FieldResult<bool> {
+        let (_, organization) = ctx.require_organization(&MANAGERS)?;
+
+        Ok(crate::organization::change_role(ctx, organization, account, &role).await?)
+    }
+
+    /// Removes a member of the active organization, any member may remove themselves
+    async fn remove_member(ctx: &Context, account: Uuid) -> FieldResult<bool> {
+        let (session, organization) = ctx.require_organization(&ROLES)?;
+
+        Ok(crate::organization::remove_member(ctx, session, organization, account).await?)
+    }
+}
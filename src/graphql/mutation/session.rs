@@ -0,0 +1,42 @@
+use crate::auth;
+use crate::graphql::Context;
+use juniper::FieldResult;
+
+pub struct SessionMutation;
+
+#[juniper::graphql_object(Context = Context)]
+impl SessionMutation {
+    async fn logout(ctx: &Context) -> FieldResult<bool> {
+        ctx.session()
+            .ok_or(auth::AuthError::InvalidCredentials)?
+            .logout()
+            .await?;
+
+        Ok(true)
+    }
+
+    /// Ends an impersonation by logging its session out, false for ordinary sessions
+    async fn stop_impersonating(ctx: &Context) -> FieldResult<bool> {
+        let session = ctx.session().ok_or(auth::AuthError::InvalidCredentials)?;
+
+        Ok(crate::impersonation::stop(ctx, session).await?)
+    }
+
+    async fn revoke_session(ctx: &Context, id: String) -> FieldResult<bool> {
+        Ok(ctx
+            .session()
+            .ok_or(auth::AuthError::InvalidCredentials)?
+            .revoke_session(&id)
+            .await?)
+    }
+
+    async fn revoke_all_other_sessions(ctx: &Context) -> FieldResult<i32> {
+        let revoked = ctx
+            .session()
+            .ok_or(auth::AuthError::InvalidCredentials)?
+            .revoke_other_sessions()
+            .await?;
+
+        Ok(revoked as i32)
+    }
+}
@@ -0,0 +1,47 @@
+use crate::auth;
+use crate::graphql::Context;
+use juniper::FieldResult;
+
+#[derive(juniper::GraphQLObject, Debug)]
+pub struct TotpEnrollment {
+    /// `otpauth://` URI to render as a QR code
+    uri: String,
+    /// Base32 encoded secret for manual entry
+    secret: String,
+}
+
+pub struct TwoFactorMutation;
+
+#[juniper::graphql_object(Context = Context)]
+impl TwoFactorMutation {
+    async fn enroll_totp(ctx: &Context) -> FieldResult<TotpEnrollment> {
+        let session = ctx.session().ok_or(auth::AuthError::InvalidCredentials)?;
+        let enrollment = crate::two_factor::enroll(ctx, session).await?;
+
+        Ok(TotpEnrollment {
+            uri: enrollment.uri,
+            secret: enrollment.secret,
+        })
+    }
+
+    /// Enables TOTP, returning the recovery codes which are not shown again
+    async fn confirm_totp(ctx: &Context, code: String) -> FieldResult<Vec<String>> {
+        let session = ctx.session().ok_or(auth::AuthError::InvalidCredentials)?;
+
+        Ok(crate::two_factor::confirm(ctx, session, &code).await?)
+    }
+
+    async fn disable_totp(ctx: &Context, code: String) -> FieldResult<bool> {
+        let session = ctx.session().ok_or(auth::AuthError::InvalidCredentials)?;
+        crate::two_factor::disable(ctx, session, &code).await?;
+
+        Ok(true)
+    }
+
+    /// Invalidates every previous recovery code, requires a current TOTP code
+    async fn regenerate_recovery_codes(ctx: &Context, code: String) -> FieldResult<Vec<String>> {
+        let session = ctx.session().ok_or(auth::AuthError::InvalidCredentials)?;
+
+        Ok(crate::two_factor::regenerate_recovery_codes(ctx, session, &code).await?)
+    }
+}
@@ -1,17 +1,13 @@
-use crate::{
-    auth,
-    graphql::{context::Context, query::Query},
-    model,
-};
+use crate::{graphql::Context, model};
 use juniper::FieldResult;
 
-#[juniper::graphql_object(Context = Context)]
-impl Query {
-    pub async fn accounts(ctx: &Context) -> FieldResult<Vec<model::Account>> {
-        if ctx.is_authenticated() {
-            Ok(crate::sql::account::get_all_accounts(ctx.database()).await?)
-        } else {
-            Err(auth::AuthError::InvalidCredentials.into())
-        }
-    }
+pub async fn accounts(ctx: &Context) -> FieldResult<Vec<model::Account>> {
+    ctx.require_first_party()?;
+    ctx.require_permission("accounts:read")?;
+
+    let mut transaction = ctx.transaction().await?;
+    let accounts = crate::sql::account::get_all_accounts(&mut transaction).await?;
+    transaction.commit().await?;
+
+    Ok(accounts)
 }
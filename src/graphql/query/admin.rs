@@ -0,0 +1,68 @@
+use crate::{admin, audit, graphql::Context, model, sql::audit_event::AuditEventFilter};
+use chrono::{DateTime, Utc};
+use juniper::FieldResult;
+use uuid::Uuid;
+
+#[derive(juniper::GraphQLInputObject, Debug)]
+pub struct AuditEventsFilter {
+    account: Option<Uuid>,
+    /// Exact event name, such as `login.failed`
+    event: Option<String>,
+    /// Inclusive lower bound of the creation time
+    from: Option<DateTime<Utc>>,
+    /// Exclusive upper bound of the creation time
+    until: Option<DateTime<Utc>>,
+}
+
+pub struct AdminQuery;
+
+#[juniper::graphql_object(Context = Context)]
+impl AdminQuery {
+    /// Accounts whose email starts with `emailPrefix`, ordered by email
+    async fn accounts(
+        ctx: &Context,
+        email_prefix: String,
+        first: Option<i32>,
+    ) -> FieldResult<Vec<model::Account>> {
+        let mut transaction = ctx.transaction().await?;
+        let accounts = crate::sql::account::search_accounts(
+            &mut transaction,
+            &email_prefix,
+            admin::search_limit(first),
+        )
+        .await?;
+        transaction.commit().await?;
+
+        Ok(accounts)
+    }
+
+    /// The security audit log, newest events first
+    async fn audit_events(
+        ctx: &Context,
+        filter: Option<AuditEventsFilter>,
+        first: Option<i32>,
+        offset: Option<i32>,
+    ) -> FieldResult<Vec<model::AuditEvent>> {
+        ctx.require_permission("audit:read")?;
+        let filter = filter.unwrap_or(AuditEventsFilter {
+            account: None,
+            event: None,
+            from: None,
+            until: None,
+        });
+
+        // Read on the API's own role, api_tenant holds no grant on the log.
+        Ok(crate::sql::audit_event::get_audit_events(
+            ctx.database(),
+            AuditEventFilter {
+                account: filter.account,
+                event: filter.event.as_deref(),
+                from: filter.from,
+                until: filter.until,
+            },
+            audit::page_limit(first),
+            offset.unwrap_or(0).max(0) as i64,
+        )
+        .await?)
+    }
+}
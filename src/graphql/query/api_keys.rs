@@ -0,0 +1,13 @@
+use crate::{auth, graphql::Context, model};
+use juniper::FieldResult;
+
+pub async fn api_keys(ctx: &Context) -> FieldResult<Vec<model::ApiKey>> {
+    let session = ctx.session().ok_or(auth::AuthError::InvalidCredentials)?;
+
+    let mut transaction = ctx.transaction().await?;
+    let api_keys =
+        crate::sql::api_key::get_api_keys(&mut transaction, session.account_id()).await?;
+    transaction.commit().await?;
+
+    Ok(api_keys)
+}
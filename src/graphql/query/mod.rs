@@ -1,2 +1,67 @@
 mod accounts;
+mod admin;
+mod api_keys;
+mod organizations;
+mod sessions;
+mod whoami;
+
+use crate::{graphql::Context, model};
+use admin::AdminQuery;
+use juniper::FieldResult;
+
 pub struct Query;
+
+#[juniper::graphql_object(Context = Context)]
+impl Query {
+    async fn accounts(ctx: &Context) -> FieldResult<Vec<model::Account>> {
+        ctx.require_scope("read")?;
+        accounts::accounts(ctx).await
+    }
+
+    /// Account lookups for staff
+    fn admin(ctx: &Context) -> FieldResult<AdminQuery> {
+        ctx.require_first_party()?;
+        ctx.require_scope("read")?;
+        ctx.require_permission("accounts:read")?;
+        Ok(AdminQuery)
+    }
+
+    /// The account behind the request, and who is impersonating it if anyone
+    async fn whoami(ctx: &Context) -> FieldResult<whoami::Whoami> {
+        ctx.require_scope("read")?;
+        whoami::whoami(ctx).await
+    }
+
+    async fn sessions(ctx: &Context) -> FieldResult<Vec<model::Session>> {
+        ctx.require_scope("read")?;
+        sessions::sessions(ctx).await
+    }
+
+    async fn api_keys(ctx: &Context) -> FieldResult<Vec<model::ApiKey>> {
+        ctx.require_scope("read")?;
+        api_keys::api_keys(ctx).await
+    }
+
+    async fn organizations(ctx: &Context) -> FieldResult<Vec<model::Organization>> {
+        ctx.require_scope("read")?;
+        organizations::organizations(ctx).await
+    }
+
+    /// Organization the session acts in, resolvers scope tenant data to it
+    async fn active_organization(ctx: &Context) -> FieldResult<Option<model::Organization>> {
+        ctx.require_scope("read")?;
+        organizations::active_organization(ctx).await
+    }
+
+    /// Members of the active organization
+    async fn members(ctx: &Context) -> FieldResult<Vec<model::Member>> {
+        ctx.require_scope("read")?;
+        organizations::members(ctx).await
+    }
+
+    /// Pending invitations of the active organization
+    async fn invitations(ctx: &Context) -> FieldResult<Vec<model::Invitation>> {
+        ctx.require_scope("read")?;
+        organizations::invitations(ctx).await
+    }
+}
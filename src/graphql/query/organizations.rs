@@ -0,0 +1,55 @@
+use crate::organization::{MANAGERS, ROLES};
+use crate::{auth, graphql::Context, model};
+use juniper::FieldResult;
+
+pub async fn organizations(ctx: &Context) -> FieldResult<Vec<model::Organization>> {
+    let session = ctx.session().ok_or(auth::AuthError::InvalidCredentials)?;
+
+    let mut transaction = ctx.transaction().await?;
+    let organizations =
+        crate::sql::organization::get_account_organizations(&mut transaction, session.account_id())
+            .await?;
+    transaction.commit().await?;
+
+    Ok(organizations)
+}
+
+pub async fn active_organization(ctx: &Context) -> FieldResult<Option<model::Organization>> {
+    let session = ctx.session().ok_or(auth::AuthError::InvalidCredentials)?;
+    let organization = match session.organization() {
+        Some(organization) => organization,
+        None => return Ok(None),
+    };
+
+    let mut transaction = ctx.transaction().await?;
+    let organization = crate::sql::organization::get_organization(
+        &mut transaction,
+        organization.id,
+        session.account_id(),
+    )
+    .await?;
+    transaction.commit().await?;
+
+    Ok(organization)
+}
+
+pub async fn members(ctx: &Context) -> FieldResult<Vec<model::Member>> {
+    let (_, organization) = ctx.require_organization(&ROLES)?;
+
+    let mut transaction = ctx.transaction().await?;
+    let members = crate::sql::organization::get_members(&mut transaction, organization.id).await?;
+    transaction.commit().await?;
+
+    Ok(members)
+}
+
+pub async fn invitations(ctx: &Context) -> FieldResult<Vec<model::Invitation>> {
+    let (_, organization) = ctx.require_organization(&MANAGERS)?;
+
+    let mut transaction = ctx.transaction().await?;
+    let invitations =
+        crate::sql::organization::get_invitations(&mut transaction, organization.id).await?;
+    transaction.commit().await?;
+
+    Ok(invitations)
+}
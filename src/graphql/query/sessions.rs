@@ -0,0 +1,13 @@
+use crate::{auth, graphql::Context, model};
+use juniper::FieldResult;
+
+pub async fn sessions(ctx: &Context) -> FieldResult<Vec<model::Session>> {
+    let session = ctx.session().ok_or(auth::AuthError::InvalidCredentials)?;
+
+    let mut transaction = ctx.transaction().await?;
+    let sessions =
+        crate::sql::session::get_active_sessions(&mut transaction, session.account_id()).await?;
+    transaction.commit().await?;
+
+    Ok(sessions)
+}
@@ -0,0 +1,30 @@
+use crate::{auth, graphql::Context, model};
+use juniper::FieldResult;
+use uuid::Uuid;
+
+#[derive(juniper::GraphQLObject, Debug)]
+pub struct Whoami {
+    account: model::Account,
+    /// Staff account acting as `account`, if the session is an impersonation
+    impersonator: Option<model::Account>,
+    roles: Vec<String>,
+    active_organization: Option<Uuid>,
+}
+
+pub async fn whoami(ctx: &Context) -> FieldResult<Whoami> {
+    let session = ctx.session().ok_or(auth::AuthError::InvalidCredentials)?;
+    // Read on the API's own role, the policies would hide the staff account from the tenant.
+    let impersonator = match session.impersonator() {
+        Some(impersonator) => {
+            Some(crate::sql::account::get_account_by_id(ctx.database(), impersonator).await?)
+        }
+        None => None,
+    };
+
+    Ok(Whoami {
+        account: session.account().await?,
+        impersonator,
+        roles: session.roles().to_vec(),
+        active_organization: session.organization().map(|organization| organization.id),
+    })
+}
@@ -31,6 +31,24 @@ where
     Ok(bincode::deserialize(&bytes)?)
 }
 
+/// Gets and deletes `key` in one transaction, so of concurrent callers only one receives the
+/// value. Meant for anything that must be used once, like challenges and authorization codes.
+pub async fn take<T>(con: &mut MultiplexedConnection, key: &str) -> anyhow::Result<T>
+where
+    T: DeserializeOwned,
+{
+    let (bytes,): (Vec<u8>,) = redis::pipe()
+        .atomic()
+        .cmd("GET")
+        .arg(key)
+        .cmd("DEL")
+        .arg(key)
+        .ignore()
+        .query_async(con)
+        .await?;
+    Ok(bincode::deserialize(&bytes)?)
+}
+
 pub async fn set_ex<'a, K, T>(
     con: &mut MultiplexedConnection,
     key: K,
@@ -45,3 +63,52 @@ where
 
     Ok(())
 }
+
+/// Sets `key` unless it already exists, returning whether it was set.
+pub async fn set_nx_ex<'a, K, T>(
+    con: &mut MultiplexedConnection,
+    key: K,
+    value: &T,
+    seconds: usize,
+) -> anyhow::Result<bool>
+where
+    K: redis::ToRedisArgs + Send + Sync + 'a,
+    T: Serialize,
+{
+    let set: Option<String> = redis::cmd("SET")
+        .arg(key)
+        .arg(bincode::serialize(value)?)
+        .arg("NX")
+        .arg("EX")
+        .arg(seconds)
+        .query_async(con)
+        .await?;
+
+    Ok(set.is_some())
+}
+
+pub async fn del<'a, K>(con: &mut MultiplexedConnection, key: K) -> anyhow::Result<()>
+where
+    K: redis::ToRedisArgs + Send + Sync + 'a,
+{
+    con.del(key).await?;
+
+    Ok(())
+}
+
+pub async fn del_matching(con: &mut MultiplexedConnection, pattern: &str) -> anyhow::Result<()> {
+    let keys: Vec<String> = {
+        let mut iter: redis::AsyncIter<String> = con.scan_match(pattern).await?;
+        let mut keys = Vec::new();
+        while let Some(key) = iter.next_item().await {
+            keys.push(key);
+        }
+        keys
+    };
+
+    if !keys.is_empty() {
+        con.del(keys).await?;
+    }
+
+    Ok(())
+}
@@ -1,2 +1,4 @@
 pub mod cache;
 pub mod problem;
+pub mod rate_limit;
+pub mod token;
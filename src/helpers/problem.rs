@@ -1,11 +1,18 @@
-use crate::auth;
+use crate::{auth, helpers::rate_limit::RateLimited, oauth::OauthError};
 use http_api_problem::HttpApiProblem as Problem;
 use std::convert::Infallible;
 use warp::http;
 use warp::{Rejection, Reply};
 
 pub fn build<E: Into<anyhow::Error>>(err: E) -> Rejection {
-    warp::reject::custom(pack(err.into()))
+    let err = err.into();
+
+    // Kept as is so that `unpack` can still attach the `Retry-After` header.
+    if let Some(limited) = err.downcast_ref::<RateLimited>() {
+        return warp::reject::custom(*limited);
+    }
+
+    warp::reject::custom(pack(err))
 }
 
 pub fn pack(err: anyhow::Error) -> Problem {
@@ -21,12 +28,62 @@ pub fn pack(err: anyhow::Error) -> Problem {
                     .set_status(http::StatusCode::UNAUTHORIZED)
                     .set_detail("The passed credentials were invalid.")
             }
+            auth::AuthError::EmailNotVerified => {
+                return Problem::new("Email not verified.")
+                    .set_status(http::StatusCode::FORBIDDEN)
+                    .set_detail("The email address of this account has not been verified yet.")
+            }
+            auth::AuthError::InsufficientScope => {
+                return Problem::new("Insufficient scope.")
+                    .set_status(http::StatusCode::FORBIDDEN)
+                    .set_detail("The access token was not granted the scope this requires.")
+            }
+            auth::AuthError::Forbidden => {
+                return Problem::new("Forbidden.")
+                    .set_status(http::StatusCode::FORBIDDEN)
+                    .set_detail("The account lacks the permission this requires.")
+            }
+            auth::AuthError::NoOrganization => {
+                return Problem::new("No active organization.")
+                    .set_status(http::StatusCode::FORBIDDEN)
+                    .set_detail("Switch to an organization the account is a member of first.")
+            }
+            auth::AuthError::AccountDisabled => {
+                return Problem::new("Account disabled.")
+                    .set_status(http::StatusCode::FORBIDDEN)
+                    .set_detail("The account was disabled, contact support.")
+            }
+            auth::AuthError::Impersonating => {
+                return Problem::new("Impersonating.")
+                    .set_status(http::StatusCode::FORBIDDEN)
+                    .set_detail("Credentials and grants can't be changed while impersonating an account.")
+            }
             auth::AuthError::ArgonError => (),
         }
     }
 
+    if let Some(err) = err.downcast_ref::<OauthError>() {
+        let status = match err {
+            OauthError::InvalidClient => http::StatusCode::UNAUTHORIZED,
+            _ => http::StatusCode::BAD_REQUEST,
+        };
+        return Problem::new(err.to_string())
+            .set_status(status)
+            .set_detail("The OAuth request was rejected, see RFC 6749 section 5.2.");
+    }
+
+    if let Some(limited) = err.downcast_ref::<RateLimited>() {
+        return rate_limited(limited);
+    }
+
     if let Some(err) = err.downcast_ref::<biscuit::errors::Error>() {
         if let biscuit::errors::Error::ValidationError(e) = err {
+            if let biscuit::errors::ValidationError::Expired(_) = e {
+                return Problem::new("Expired JWT token.")
+                    .set_status(http::StatusCode::UNAUTHORIZED)
+                    .set_detail("The passed JWT token has expired, please authenticate again.");
+            }
+
             return Problem::new("Invalid JWT token.")
                 .set_status(http::StatusCode::BAD_REQUEST)
                 .set_detail(format!("The passed JWT token were invalid. {}", e));
@@ -37,7 +94,16 @@ pub fn pack(err: anyhow::Error) -> Problem {
     Problem::with_title_and_type_from_status(http::StatusCode::INTERNAL_SERVER_ERROR)
 }
 
-fn reply_from_problem(problem: &Problem) -> impl Reply {
+fn rate_limited(limited: &RateLimited) -> Problem {
+    Problem::new("Too many requests.")
+        .set_status(http::StatusCode::TOO_MANY_REQUESTS)
+        .set_detail(format!(
+            "Too many requests, retry after {} seconds.",
+            limited.retry_after
+        ))
+}
+
+fn reply_from_problem(problem: &Problem) -> warp::reply::Response {
     let code = problem
         .status
         .unwrap_or(http::StatusCode::INTERNAL_SERVER_ERROR);
@@ -49,6 +115,7 @@ fn reply_from_problem(problem: &Problem) -> impl Reply {
         http::header::CONTENT_TYPE,
         http_api_problem::PROBLEM_JSON_MEDIA_TYPE,
     )
+    .into_response()
 }
 
 pub async fn unpack(rejection: Rejection) -> Result<impl Reply, Infallible> {
@@ -57,6 +124,16 @@ pub async fn unpack(rejection: Rejection) -> Result<impl Reply, Infallible> {
         reply_from_problem(&problem)
     } else if let Some(problem) = rejection.find::<Problem>() {
         reply_from_problem(problem)
+    } else if let Some(limited) = rejection.find::<RateLimited>() {
+        let mut reply = reply_from_problem(&rate_limited(limited));
+        let headers = reply.headers_mut();
+        headers.insert(http::header::RETRY_AFTER, limited.retry_after.into());
+        if let Some(limit) = limited.limit {
+            headers.insert("ratelimit-limit", limit.into());
+            headers.insert("ratelimit-remaining", 0u64.into());
+            headers.insert("ratelimit-reset", limited.retry_after.into());
+        }
+        reply
     } else if let Some(e) = rejection.find::<warp::filters::body::BodyDeserializeError>() {
         let problem = Problem::new("Invalid Request Body.")
             .set_status(http::StatusCode::BAD_REQUEST)
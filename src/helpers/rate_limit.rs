@@ -0,0 +1,327 @@
+use crate::{
+    environment::Environment,
+    helpers::{problem, token},
+};
+use chrono::Utc;
+use redis::aio::MultiplexedConnection;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+use uuid::Uuid;
+use warp::{filters::BoxedFilter, Filter, Rejection, Reply};
+
+#[derive(Error, Debug, Clone, Copy)]
+#[error("rate limit exceeded, retry after {retry_after} seconds")]
+pub struct RateLimited {
+    pub retry_after: u64,
+    /// Set when the limit is a token bucket, so the `RateLimit-*` headers can be sent along.
+    pub limit: Option<u64>,
+}
+
+impl warp::reject::Reject for RateLimited {}
+
+const SLIDING_WINDOW: &str = r#"
+local now = tonumber(ARGV[1])
+local window = tonumber(ARGV[2])
+redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', now - window)
+if redis.call('ZCARD', KEYS[1]) < tonumber(ARGV[3]) then
+  redis.call('ZADD', KEYS[1], now, ARGV[4])
+  redis.call('PEXPIRE', KEYS[1], window)
+  return 0
+end
+local oldest = redis.call('ZRANGE', KEYS[1], 0, 0, 'WITHSCORES')
+return math.max(1, (tonumber(oldest[2]) or now) + window - now)
+"#;
+
+/// Records a hit for `key` unless `limit` hits already fell into the trailing `window` seconds.
+/// Hits are kept in a sorted set scored by their timestamp in milliseconds. Denied hits are not
+/// recorded, so retrying doesn't push the window further out and lock a victim's email for good.
+pub async fn sliding_window(
+    con: &mut MultiplexedConnection,
+    key: &str,
+    limit: u64,
+    window: u64,
+) -> anyhow::Result<()> {
+    let now = Utc::now().timestamp_millis();
+    let member = format!("{}:{}", now, uuid::Uuid::new_v4());
+
+    let retry_after: i64 = redis::Script::new(SLIDING_WINDOW)
+        .key(key)
+        .arg(now)
+        .arg(window * 1000)
+        .arg(limit)
+        .arg(member)
+        .invoke_async(con)
+        .await?;
+
+    if retry_after == 0 {
+        return Ok(());
+    }
+
+    Err(RateLimited {
+        retry_after: (retry_after as u64 + 999) / 1000,
+        limit: None,
+    }
+    .into())
+}
+
+/// A bucket holding up to `burst` tokens, refilled completely over `period` seconds.
+#[derive(Clone, Copy, Debug)]
+pub struct Quota {
+    pub burst: u64,
+    pub period: u64,
+}
+
+impl Quota {
+    fn rate(&self) -> f64 {
+        self.burst as f64 / self.period.max(1) as f64
+    }
+
+    fn decide(&self, tokens: f64, allowed: bool) -> Result<RateLimit, RateLimited> {
+        let seconds = |tokens: f64| (tokens / self.rate()).ceil().max(0.0) as u64;
+
+        if allowed {
+            Ok(RateLimit {
+                limit: self.burst,
+                remaining: tokens.floor() as u64,
+                reset: seconds(self.burst as f64 - tokens),
+            })
+        } else {
+            Err(RateLimited {
+                retry_after: seconds(1.0 - tokens),
+                limit: Some(self.burst),
+            })
+        }
+    }
+}
+
+/// What a request is accounted to.
+#[derive(Clone, Debug)]
+pub enum Key {
+    Ip(IpAddr),
+    Account(Uuid),
+    ApiKey(String),
+    Anonymous,
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Key::Ip(ip) => write!(f, "ip:{}", ip),
+            Key::Account(id) => write!(f, "account:{}", id),
+            Key::ApiKey(api_key) => write!(f, "api_key:{}", token::hash(api_key)),
+            Key::Anonymous => write!(f, "anonymous"),
+        }
+    }
+}
+
+pub fn by_ip() -> BoxedFilter<(Key,)> {
+    warp::addr::remote()
+        .map(|addr: Option<SocketAddr>| addr.map_or(Key::Anonymous, |addr| Key::Ip(addr.ip())))
+        .boxed()
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimit {
+    pub limit: u64,
+    pub remaining: u64,
+    pub reset: u64,
+}
+
+impl RateLimit {
+    pub fn apply(self, reply: impl Reply) -> impl Reply {
+        let reply = warp::reply::with_header(reply, "ratelimit-limit", self.limit.to_string());
+        let reply =
+            warp::reply::with_header(reply, "ratelimit-remaining", self.remaining.to_string());
+        warp::reply::with_header(reply, "ratelimit-reset", self.reset.to_string())
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: i64,
+}
+
+const TOKEN_BUCKET: &str = r#"
+local capacity = tonumber(ARGV[1])
+local rate = tonumber(ARGV[2])
+local now = tonumber(ARGV[3])
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'updated')
+local tokens = tonumber(bucket[1]) or capacity
+local updated = tonumber(bucket[2]) or now
+tokens = math.min(capacity, tokens + math.max(0, now - updated) * rate)
+local allowed = 0
+if tokens >= 1 then
+  tokens = tokens - 1
+  allowed = 1
+end
+redis.call('HMSET', KEYS[1], 'tokens', tostring(tokens), 'updated', now)
+redis.call('PEXPIRE', KEYS[1], math.max(1, math.ceil((capacity - tokens) / rate)))
+return {allowed, tostring(tokens)}
+"#;
+
+/// Token bucket rate limiter kept in Redis, falling back to buckets local to this process while
+/// Redis is unreachable.
+#[derive(Clone)]
+pub struct Limiter {
+    env: Environment,
+    local: Arc<Mutex<HashMap<String, Bucket>>>,
+}
+
+impl Limiter {
+    pub fn new(env: Environment) -> Self {
+        Self {
+            env,
+            local: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Rejects requests once the bucket of the key extracted by `key` within `scope` is empty,
+    /// extracting what is left of it for `RateLimit::apply`.
+    pub fn filter<F>(
+        &self,
+        scope: &'static str,
+        quota: Quota,
+        key: F,
+    ) -> impl Filter<Extract = (RateLimit,), Error = Rejection> + Clone
+    where
+        F: Filter<Extract = (Key,), Error = Rejection> + Clone + Send + Sync + 'static,
+    {
+        let limiter = self.clone();
+        key.and_then(move |key: Key| {
+            let limiter = limiter.clone();
+            async move {
+                limiter
+                    .limit(scope, quota, &key)
+                    .await
+                    .map_err(problem::build)
+            }
+        })
+    }
+
+    /// Like `filter`, keyed by a value extracted earlier, such as the GraphQL context, which is
+    /// passed on so that it is only built once. Values without a key are not limited.
+    pub fn filter_by<F, T>(
+        &self,
+        scope: &'static str,
+        quota: Quota,
+        extract: F,
+        key: fn(&T) -> Option<Key>,
+    ) -> impl Filter<Extract = (Option<RateLimit>, T), Error = Rejection> + Clone
+    where
+        F: Filter<Extract = (T,), Error = Rejection> + Clone + Send + Sync + 'static,
+        T: Send + 'static,
+    {
+        let limiter = self.clone();
+        extract
+            .and_then(move |value: T| {
+                let limiter = limiter.clone();
+                async move {
+                    let limit = match key(&value) {
+                        Some(key) => Some(
+                            limiter
+                                .limit(scope, quota, &key)
+                                .await
+                                .map_err(problem::build)?,
+                        ),
+                        None => None,
+                    };
+                    Ok::<_, Rejection>((limit, value))
+                }
+            })
+            .untuple_one()
+    }
+
+    /// Takes a token from the bucket of `key` within `scope`.
+    pub async fn limit(&self, scope: &str, quota: Quota, key: &Key) -> anyhow::Result<RateLimit> {
+        self.check(&format!("rate_limit:{}:{}", scope, key), quota)
+            .await
+    }
+
+    pub async fn check(&self, key: &str, quota: Quota) -> anyhow::Result<RateLimit> {
+        let (tokens, allowed) = match self.remote(key, quota).await {
+            Ok(result) => result,
+            Err(err) => {
+                tracing::warn!("rate limiting locally, redis unavailable: {:#}", err);
+                self.local(key, quota)
+            }
+        };
+
+        Ok(quota.decide(tokens, allowed)?)
+    }
+
+    async fn remote(&self, key: &str, quota: Quota) -> anyhow::Result<(f64, bool)> {
+        let mut redis = self.env.redis().await?;
+        let (allowed, tokens): (i64, String) = redis::Script::new(TOKEN_BUCKET)
+            .key(key)
+            .arg(quota.burst)
+            .arg(quota.rate() / 1000.0)
+            .arg(Utc::now().timestamp_millis())
+            .invoke_async(&mut redis)
+            .await?;
+
+        Ok((tokens.parse()?, allowed == 1))
+    }
+
+    fn local(&self, key: &str, quota: Quota) -> (f64, bool) {
+        let now = Utc::now().timestamp_millis();
+        let rate = quota.rate() / 1000.0;
+        let capacity = quota.burst as f64;
+        let mut buckets = self.local.lock().unwrap_or_else(|e| e.into_inner());
+
+        if buckets.len() > 10_000 {
+            buckets.retain(|_, bucket| {
+                bucket.tokens + (now - bucket.updated) as f64 * rate < capacity
+            });
+        }
+
+        let bucket = buckets.entry(key.to_owned()).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        bucket.tokens = (bucket.tokens + (now - bucket.updated).max(0) as f64 * rate).min(capacity);
+        bucket.updated = now;
+
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+
+        (bucket.tokens, allowed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUOTA: Quota = Quota {
+        burst: 10,
+        period: 60,
+    };
+
+    #[test]
+    fn allowed_reports_remaining_tokens() {
+        let limit = QUOTA.decide(7.5, true).unwrap();
+        assert_eq!(limit.limit, 10);
+        assert_eq!(limit.remaining, 7);
+        assert_eq!(limit.reset, 15);
+    }
+
+    #[test]
+    fn denied_waits_for_the_next_token() {
+        let limited = QUOTA.decide(0.5, false).unwrap_err();
+        assert_eq!(limited.retry_after, 3);
+        assert_eq!(limited.limit, Some(10));
+    }
+
+    #[test]
+    fn api_keys_are_not_stored_in_the_clear() {
+        let key = Key::ApiKey("secret".to_owned()).to_string();
+        assert!(key.starts_with("api_key:"));
+        assert!(!key.contains("secret"));
+    }
+}
@@ -0,0 +1,100 @@
+use crate::{
+    audit::{self, Event},
+    auth::{AuthError, Claims},
+    environment::Environment,
+    helpers::token,
+    model::session::Identity,
+    session::Session,
+    sql::account::NewSession,
+};
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+
+/// Seconds an impersonation lasts, it can't be refreshed.
+const IMPERSONATION_LIFETIME: i64 = 3600;
+
+pub struct Impersonation {
+    pub jwt: String,
+    pub csrf: String,
+    pub expiry: DateTime<Utc>,
+}
+
+/// Opens a session of `account` on behalf of the staff member, flagged with their id so it shows
+/// in the account's sessions and can't change its credentials. Other admins can't be impersonated.
+pub async fn start(
+    env: &Environment,
+    session: &Session,
+    account: Uuid,
+) -> anyhow::Result<Impersonation> {
+    session.require_not_impersonating()?;
+    let roles = crate::sql::role::get_account_roles(env.database(), account).await?;
+    crate::admin::check_target(session.account_id(), account, &roles)?;
+    if crate::sql::account::get_account_by_id(env.database(), account)
+        .await?
+        .disabled_at
+        .is_some()
+    {
+        return Err(AuthError::AccountDisabled.into());
+    }
+
+    let claims = Claims::generate();
+    let key = claims.session();
+    let expiry = Utc::now() + Duration::seconds(IMPERSONATION_LIFETIME);
+
+    crate::sql::account::create_session(
+        env.database(),
+        NewSession {
+            key: &key,
+            csrf: &claims.csrf(),
+            family: &key,
+            refresh_token: &token::hash(&token::generate()),
+            expiry,
+            refresh_expiry: expiry,
+            client_id: None,
+            scope: None,
+            impersonator: Some(session.account_id()),
+        },
+        account,
+        Identity::default(),
+    )
+    .await?;
+
+    audit::record(
+        env,
+        Event::ImpersonationStarted,
+        audit::Entry {
+            account: Some(account),
+            actor: Some(session.account_id()),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    Ok(Impersonation {
+        csrf: claims.csrf(),
+        jwt: env.jwt().encode(claims, expiry)?,
+        expiry,
+    })
+}
+
+/// Ends the impersonation the session belongs to, returning false for ordinary sessions.
+pub async fn stop(env: &Environment, session: &Session) -> anyhow::Result<bool> {
+    let impersonator = match session.impersonator() {
+        Some(impersonator) => impersonator,
+        None => return Ok(false),
+    };
+    session.logout().await?;
+
+    audit::record(
+        env,
+        Event::ImpersonationStopped,
+        audit::Entry {
+            account: Some(session.account_id()),
+            actor: Some(impersonator),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    Ok(true)
+}
@@ -0,0 +1,73 @@
+use crate::{
+    auth::AuthError,
+    environment::{Environment, Mail},
+    helpers::{cache, token},
+};
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+
+/// Like `password::request_reset` this never reveals whether the address belongs to an account,
+/// and mails an address at most once per `mail_interval`.
+pub async fn request(env: &Environment, email: &str) -> anyhow::Result<()> {
+    let key = format!("mail:magic_link:{}", email.to_lowercase());
+    if !cache::set_nx_ex(&mut env.redis().await?, key, &(), env.mail_interval()).await? {
+        return Ok(());
+    }
+
+    let env = env.clone();
+    let email = email.to_owned();
+    tokio::spawn(async move {
+        if let Err(err) = send(&env, &email).await {
+            tracing::error!("sending magic link failed: {:#}", err);
+        }
+    });
+
+    Ok(())
+}
+
+async fn send(env: &Environment, email: &str) -> anyhow::Result<()> {
+    let account =
+        match crate::sql::account::get_account_id_password_by_email(env.database(), email).await? {
+            Some(account) => account,
+            None => return Ok(()),
+        };
+
+    let link_token = token::generate();
+    let expiry = Utc::now() + Duration::seconds(env.magic_link_lifetime());
+
+    crate::sql::magic_link::create_magic_link(
+        env.database(),
+        &token::hash(&link_token),
+        account.id,
+        email,
+        expiry,
+    )
+    .await?;
+
+    let mut link = url::Url::parse(env.magic_link_url())?;
+    link.query_pairs_mut().append_pair("token", &link_token);
+
+    env.mailer()
+        .send(Mail {
+            to: email.to_owned(),
+            subject: "Your login link".to_owned(),
+            body: format!(
+                "Open the following link to log in, it can be used once and expires at {}.\n\n{}",
+                expiry.to_rfc2822(),
+                link
+            ),
+        })
+        .await
+}
+
+/// Consumes a link and returns its account. Receiving the link proves the address, so it is
+/// marked verified as well.
+pub async fn consume(env: &Environment, link_token: &str) -> anyhow::Result<Uuid> {
+    let link = crate::sql::magic_link::consume_magic_link(env.database(), &token::hash(link_token))
+        .await?
+        .ok_or(AuthError::InvalidCredentials)?;
+
+    crate::sql::account::mark_email_verified(env.database(), link.account, &link.email).await?;
+
+    Ok(link.account)
+}
@@ -1,18 +1,31 @@
+mod admin;
+mod api_key;
+mod audit;
 mod auth;
 mod environment;
 mod graphql;
 mod helpers;
+mod impersonation;
+mod magic_link;
 mod model;
+mod oauth;
+mod oidc;
+mod organization;
+mod password;
 mod session;
 mod sql;
+mod two_factor;
+mod verification;
+mod webauthn;
 
 use clap::Clap;
 use environment::Environment;
-use helpers::problem;
+use helpers::{problem, rate_limit};
 use hyper::server::Server;
 use listenfd::ListenFd;
 use std::convert::Infallible;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use warp::Filter;
 
 #[derive(Clap, Debug)]
@@ -32,14 +45,102 @@ pub struct Args {
 
     #[clap(required = true, long, env)]
     jwt_secret: String,
+    /// One of HS256, RS256 or ES256, asymmetric algorithms require `jwt_private_key`
+    #[clap(long, env)]
+    jwt_algorithm: Option<String>,
+    /// PEM or DER encoded PKCS#8 private key (PKCS#1 is accepted for RSA)
+    #[clap(long, env)]
+    jwt_private_key: Option<PathBuf>,
+    /// Sent as the `kid` header of every issued token
+    #[clap(long, env)]
+    jwt_key_id: Option<String>,
+    /// Keys still accepted until retired, as `<kid>:<algorithm>:<YYYY-MM-DD>:<secret or key path>`
+    #[clap(long, env, use_delimiter = true)]
+    jwt_retired_keys: Vec<String>,
+    #[clap(long, env)]
+    jwt_issuer: Option<String>,
+    #[clap(long, env)]
+    jwt_audience: Option<String>,
+    #[clap(long, env)]
+    jwt_leeway: Option<i64>,
     #[clap(required = true, long, env)]
     argon_secret: String,
     #[clap(long, env)]
     argon_iterations: Option<u32>,
     #[clap(long, env)]
     argon_memory_size: Option<u32>,
+    /// Encrypts stored TOTP secrets, two-factor enrollment is unavailable without it
+    #[clap(long, env)]
+    totp_encryption_key: Option<String>,
+    /// Issuer shown in authenticator apps
+    #[clap(long, env)]
+    totp_issuer: Option<String>,
+    /// Domain passkeys are bound to, defaults to localhost
+    #[clap(long, env)]
+    webauthn_rp_id: Option<String>,
+    /// Name shown by the browser when creating a passkey
+    #[clap(long, env)]
+    webauthn_rp_name: Option<String>,
+    /// Origin of the web app, defaults to https://<rp id>
+    #[clap(long, env)]
+    webauthn_origin: Option<String>,
+    /// OpenID Connect providers, as `<name>:<client id>:<client secret>:<issuer url>`
+    #[clap(long, env, use_delimiter = true)]
+    oidc_providers: Vec<String>,
+    /// Public URL of this API, providers redirect to `<url>/auth/oidc/<name>/callback`
+    #[clap(long, env)]
+    oidc_redirect_url: Option<String>,
+    /// Providers whose verified email addresses may log into existing accounts of that address
+    #[clap(long, env, use_delimiter = true)]
+    oidc_trusted_providers: Vec<String>,
+
     #[clap(short, long, env)]
     session_lifetime: Option<i64>,
+    #[clap(long, env)]
+    refresh_token_lifetime: Option<i64>,
+    #[clap(long, env)]
+    password_reset_lifetime: Option<i64>,
+    #[clap(long, env)]
+    email_verification_lifetime: Option<i64>,
+    #[clap(long, env)]
+    magic_link_lifetime: Option<i64>,
+    /// Link sent for magic link logins, the token is appended as `?token=`
+    #[clap(long, env)]
+    magic_link_url: Option<String>,
+    #[clap(long, env)]
+    invitation_lifetime: Option<i64>,
+    /// Link sent to invited members, the token is appended as `?token=`
+    #[clap(long, env)]
+    invitation_url: Option<String>,
+    /// Refuse logins of accounts that did not verify their email address yet
+    #[clap(long, env)]
+    require_verified_email: Option<bool>,
+
+    /// Login attempts allowed per email address and per client address within the window
+    #[clap(long, env)]
+    login_rate_limit: Option<u64>,
+    #[clap(long, env)]
+    login_rate_window: Option<u64>,
+    /// Consecutive failed logins after which an account is locked
+    #[clap(long, env)]
+    lockout_threshold: Option<i32>,
+    #[clap(long, env)]
+    lockout_duration: Option<i64>,
+    /// Requests a client may burst to, refilled over the period in seconds
+    #[clap(long, env)]
+    rate_limit_burst: Option<u64>,
+    #[clap(long, env)]
+    rate_limit_period: Option<u64>,
+    /// Seconds access tokens issued to OAuth clients are valid
+    #[clap(long, env)]
+    oauth_access_token_lifetime: Option<i64>,
+
+    /// Write outgoing mail to this directory instead of logging it
+    #[clap(long, env)]
+    mail_dir: Option<PathBuf>,
+    /// Seconds before the same address is mailed again
+    #[clap(long, env)]
+    mail_interval: Option<usize>,
 
     #[clap(default_value = "127.0.0.1:3535", env)]
     host: SocketAddr,
@@ -53,6 +154,11 @@ async fn main() -> anyhow::Result<()> {
     }
     let args = Args::parse();
     let env = Environment::new(&args).await?;
+    let limiter = rate_limit::Limiter::new(env.clone());
+    let quota = rate_limit::Quota {
+        burst: args.rate_limit_burst.unwrap_or(100u64),
+        period: args.rate_limit_period.unwrap_or(60u64),
+    };
     let env = warp::any().map(move || env.clone());
     let cors = warp::cors()
         .allow_methods(vec!["GET", "POST"])
@@ -61,35 +167,15 @@ async fn main() -> anyhow::Result<()> {
         .allow_any_origin()
         .build();
     let log = warp::log("api::request");
-    let status = warp::path("status")
-        .and(warp::get())
-        .and(warp::path::end())
-        .map(|| format!("OK"));
-    let auth = warp::path("auth")
-        .and(warp::post())
-        .and(warp::path::end())
-        .and(env.clone())
-        .and(warp::body::json())
-        .and(warp::addr::remote())
-        .and_then(|env, req, addr| async move {
-            auth::filter(env, req, addr).await.map_err(problem::build)
-        });
-    let graphql = {
-        use futures::FutureExt as _;
-        use juniper_subscriptions::Coordinator;
-        use juniper_warp::{
-            make_graphql_filter, playground_filter, subscriptions::graphql_subscriptions,
-        };
+    let credentials = {
         use serde::Deserialize;
-        use std::sync::Arc;
-        use warp::Filter;
 
         #[derive(Deserialize, Debug)]
         struct Query {
             csrf: Option<String>,
         }
 
-        let auth = warp::header("authorization")
+        warp::header("authorization")
             .or(warp::cookie("jwt"))
             .unify()
             .map(Some)
@@ -97,6 +183,10 @@ async fn main() -> anyhow::Result<()> {
             .unify()
             .and(warp::query())
             .and_then(|jwt: Option<String>, query: Query| async {
+                if let Some(token) = jwt.as_deref().and_then(|jwt| jwt.strip_prefix("Bearer ")) {
+                    return Ok(Some(auth::Credentials::Bearer(token.to_owned())));
+                }
+
                 if jwt.is_none() && query.csrf.is_none() {
                     return Ok(None);
                 }
@@ -105,12 +195,206 @@ async fn main() -> anyhow::Result<()> {
                     return Err(problem::build(auth::AuthError::InvalidCredentials));
                 }
 
-                Ok(Some((jwt.unwrap(), query.csrf.unwrap())))
+                Ok(Some(auth::Credentials::Session {
+                    jwt: jwt.unwrap(),
+                    csrf: query.csrf.unwrap(),
+                }))
+            })
+    };
+    let status = warp::path("status")
+        .and(warp::get())
+        .and(warp::path::end())
+        .map(|| format!("OK"));
+    let jwks = warp::path!(".well-known" / "jwks.json")
+        .and(warp::get())
+        .and(env.clone())
+        .map(|env: Environment| warp::reply::json(&env.jwt().jwks()));
+    let auth = warp::path("auth")
+        .and(warp::post())
+        .and(warp::path::end())
+        .and(env.clone())
+        .and(warp::body::json())
+        .and(warp::addr::remote())
+        .and_then(|env, req, addr| async move {
+            auth::filter(env, req, addr).await.map_err(problem::build)
+        });
+    let two_factor = warp::path!("auth" / "2fa")
+        .and(warp::post())
+        .and(env.clone())
+        .and(warp::body::json())
+        .and_then(
+            |env, req| async move { auth::two_factor(env, req).await.map_err(problem::build) },
+        );
+    let magic_link = warp::path!("auth" / "magic")
+        .and(warp::get())
+        .and(env.clone())
+        .and(warp::query())
+        .and(warp::addr::remote())
+        .and_then(|env, query, addr| async move {
+            auth::magic_link(env, query, addr)
+                .await
+                .map_err(problem::build)
+        });
+    let refresh = warp::path!("auth" / "refresh")
+        .and(warp::post())
+        .and(env.clone())
+        .and(warp::body::json())
+        .and(warp::addr::remote())
+        .and_then(|env, req, addr| async move {
+            auth::refresh(env, req, addr).await.map_err(problem::build)
+        });
+    let logout = warp::path!("auth" / "logout")
+        .and(warp::post())
+        .and(env.clone())
+        .and(credentials.clone())
+        .and_then(|env, credentials: Option<auth::Credentials>| async move {
+            let (jwt, csrf) = credentials
+                .and_then(auth::Credentials::into_session)
+                .ok_or_else(|| problem::build(auth::AuthError::InvalidCredentials))?;
+            auth::logout(env, jwt, csrf).await.map_err(problem::build)
+        });
+    let webauthn = {
+        let register_begin = warp::path!("register" / "begin")
+            .and(env.clone())
+            .and(credentials.clone())
+            .and_then(|env, credentials: Option<auth::Credentials>| async move {
+                let (jwt, csrf) = credentials
+                    .and_then(auth::Credentials::into_session)
+                    .ok_or_else(|| problem::build(auth::AuthError::InvalidCredentials))?;
+                auth::webauthn_register_begin(env, jwt, csrf)
+                    .await
+                    .map_err(problem::build)
+            });
+        let register_finish = warp::path!("register" / "finish")
+            .and(env.clone())
+            .and(credentials.clone())
+            .and(warp::body::json())
+            .and_then(
+                |env, credentials: Option<auth::Credentials>, req| async move {
+                    let (jwt, csrf) = credentials
+                        .and_then(auth::Credentials::into_session)
+                        .ok_or_else(|| problem::build(auth::AuthError::InvalidCredentials))?;
+                    auth::webauthn_register_finish(env, jwt, csrf, req)
+                        .await
+                        .map_err(problem::build)
+                },
+            );
+        let login_begin = warp::path!("login" / "begin")
+            .and(env.clone())
+            .and(warp::body::json())
+            .and_then(|env, req| async move {
+                auth::webauthn_login_begin(env, req)
+                    .await
+                    .map_err(problem::build)
+            });
+        let login_finish = warp::path!("login" / "finish")
+            .and(env.clone())
+            .and(warp::body::json())
+            .and(warp::addr::remote())
+            .and_then(|env, req, addr| async move {
+                auth::webauthn_login_finish(env, req, addr)
+                    .await
+                    .map_err(problem::build)
+            });
+
+        warp::path!("auth" / "webauthn" / ..).and(warp::post()).and(
+            register_begin
+                .or(register_finish)
+                .or(login_begin)
+                .or(login_finish),
+        )
+    };
+    let oidc = {
+        let start = warp::path!("auth" / "oidc" / String / "start")
+            .and(warp::get())
+            .and(env.clone())
+            .and_then(|provider, env| async move {
+                auth::oidc_start(env, provider)
+                    .await
+                    .map_err(problem::build)
+            });
+        let callback = warp::path!("auth" / "oidc" / String / "callback")
+            .and(warp::get())
+            .and(env.clone())
+            .and(warp::query())
+            .and(warp::cookie::optional(oidc::STATE_COOKIE))
+            .and(warp::addr::remote())
+            .and_then(|provider, env, callback, state, addr| async move {
+                auth::oidc_callback(env, provider, callback, state, addr)
+                    .await
+                    .map_err(problem::build)
             });
 
+        start.or(callback)
+    };
+    let oauth = {
+        let authorize = warp::path!("oauth" / "authorize")
+            .and(env.clone())
+            .and(credentials.clone())
+            .and(warp::body::json())
+            .and_then(
+                |env: Environment, credentials: Option<auth::Credentials>, req| async move {
+                    let (jwt, csrf) = credentials
+                        .and_then(auth::Credentials::into_session)
+                        .ok_or_else(|| problem::build(auth::AuthError::InvalidCredentials))?;
+                    let session = session::Session::new(env.clone(), &jwt, &csrf)
+                        .await
+                        .map_err(problem::build)?;
+                    oauth::authorize(&env, &session, req)
+                        .await
+                        .map(|authorization| warp::reply::json(&authorization))
+                        .map_err(problem::build)
+                },
+            );
+        let token = warp::path!("oauth" / "token")
+            .and(env.clone())
+            .and(warp::header::optional("authorization"))
+            .and(warp::body::form())
+            .and_then(|env: Environment, authorization, req| async move {
+                oauth::token(&env, authorization, req)
+                    .await
+                    .map(|token| {
+                        warp::reply::with_header(
+                            warp::reply::json(&token),
+                            warp::http::header::CACHE_CONTROL,
+                            "no-store",
+                        )
+                    })
+                    .map_err(problem::build)
+            });
+        let introspect = warp::path!("oauth" / "introspect")
+            .and(env.clone())
+            .and(warp::header::optional("authorization"))
+            .and(warp::body::form())
+            .and_then(|env: Environment, authorization, req| async move {
+                oauth::introspect(&env, authorization, req)
+                    .await
+                    .map(|introspection| warp::reply::json(&introspection))
+                    .map_err(problem::build)
+            });
+        let revoke = warp::path!("oauth" / "revoke")
+            .and(env.clone())
+            .and(warp::header::optional("authorization"))
+            .and(warp::body::form())
+            .and_then(|env: Environment, authorization, req| async move {
+                oauth::revoke(&env, authorization, req)
+                    .await
+                    .map(|_| warp::reply())
+                    .map_err(problem::build)
+            });
+
+        warp::post().and(authorize.or(token).or(introspect).or(revoke))
+    };
+    let graphql = {
+        use futures::FutureExt as _;
+        use juniper_subscriptions::Coordinator;
+        use juniper_warp::{playground_filter, subscriptions::graphql_subscriptions};
+        use std::sync::Arc;
+        use warp::Filter;
+
         let context = warp::any()
             .and(env.clone())
-            .and(auth)
+            .and(credentials.clone())
             .and_then(|env, auth| async {
                 graphql::Context::new(env, auth)
                     .await
@@ -120,10 +404,40 @@ async fn main() -> anyhow::Result<()> {
 
         let coordinator = Arc::new(Coordinator::new(graphql::schema()));
 
+        let schema = Arc::new(graphql::schema());
+
+        // Addresses are limited before the context is built, so a flood costs no database
+        // queries. Accounts and API keys then draw from buckets of their own.
         let query = warp::path("query")
             .and(warp::post())
             .and(warp::path::end())
-            .and(make_graphql_filter(graphql::schema(), context.clone()));
+            .and(limiter.filter("graphql:ip", quota, rate_limit::by_ip()))
+            .and(limiter.filter_by(
+                "graphql",
+                quota,
+                context.clone(),
+                graphql::Context::rate_limit_key,
+            ))
+            .and(warp::body::json())
+            .and_then(
+                move |ip_limit: rate_limit::RateLimit,
+                      limit: Option<rate_limit::RateLimit>,
+                      context: graphql::Context,
+                      request: juniper::http::GraphQLBatchRequest| {
+                    let schema = Arc::clone(&schema);
+                    async move {
+                        let response = request.execute(&schema, &context).await;
+                        let status = if response.is_ok() {
+                            warp::http::StatusCode::OK
+                        } else {
+                            warp::http::StatusCode::BAD_REQUEST
+                        };
+                        Ok::<_, warp::Rejection>(limit.unwrap_or(ip_limit).apply(
+                            warp::reply::with_status(warp::reply::json(&response), status),
+                        ))
+                    }
+                },
+            );
 
         let subscriptions = warp::path("subscriptions")
             .and(warp::path::end())
@@ -154,7 +468,15 @@ async fn main() -> anyhow::Result<()> {
     };
 
     let svc = warp::service(
-        auth.or(status)
+        auth.or(two_factor)
+            .or(magic_link)
+            .or(refresh)
+            .or(logout)
+            .or(webauthn)
+            .or(oidc)
+            .or(oauth)
+            .or(jwks)
+            .or(status)
             .or(graphql)
             .recover(problem::unpack)
             .with(cors)
@@ -13,6 +13,10 @@ pub struct Account {
     #[serde(skip_serializing)]
     pub password: Redacted<String>,
 
+    pub email_verified_at: Option<DateTime<Utc>>,
+    /// Disabled accounts can neither log in nor use existing sessions
+    pub disabled_at: Option<DateTime<Utc>>,
+
     pub created_at: DateTime<Utc>,
     pub updated_at: Option<DateTime<Utc>>,
 }
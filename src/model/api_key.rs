@@ -0,0 +1,16 @@
+use chrono::{DateTime, Utc};
+use juniper::GraphQLObject;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Clone, Serialize, Deserialize, GraphQLObject, Debug)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub name: String,
+    /// Leading part of the key, to tell keys apart
+    pub prefix: String,
+    pub scope: String,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub expiry: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
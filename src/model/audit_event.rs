@@ -0,0 +1,18 @@
+use chrono::{DateTime, Utc};
+use juniper::GraphQLObject;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Clone, Serialize, Deserialize, GraphQLObject, Debug)]
+pub struct AuditEvent {
+    pub id: Uuid,
+    /// Dotted name such as `login.failed` or `admin.account_disabled`
+    pub event: String,
+    /// Account the event concerns
+    pub account: Option<Uuid>,
+    /// Account that caused the event, when it wasn't the account itself
+    pub actor: Option<Uuid>,
+    pub ip: Option<String>,
+    pub detail: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
@@ -1,6 +1,12 @@
 pub mod account;
+pub mod api_key;
+pub mod audit_event;
+pub mod organization;
 mod redacted;
 pub mod session;
 
 pub use account::Account;
+pub use api_key::ApiKey;
+pub use audit_event::AuditEvent;
+pub use organization::{Invitation, Member, Organization};
 pub use session::Session;
@@ -0,0 +1,32 @@
+use chrono::{DateTime, Utc};
+use juniper::GraphQLObject;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Clone, Serialize, Deserialize, GraphQLObject, Debug)]
+pub struct Organization {
+    pub id: Uuid,
+    pub name: String,
+    /// Role of the current account in the organization
+    pub role: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Serialize, Deserialize, GraphQLObject, Debug)]
+pub struct Member {
+    pub account: Uuid,
+    pub email: String,
+    /// One of `owner`, `admin` or `member`
+    pub role: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Serialize, Deserialize, GraphQLObject, Debug)]
+pub struct Invitation {
+    pub id: Uuid,
+    pub email: String,
+    pub role: String,
+    pub expiry: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
@@ -1,3 +1,4 @@
+use crate::helpers::token;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::{types::json::Json, FromRow};
@@ -14,6 +15,62 @@ pub struct Session {
     pub invalidated: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: Option<DateTime<Utc>>,
+    pub family: Option<String>,
+    pub refresh_token: Option<String>,
+    pub refresh_expiry: Option<DateTime<Utc>>,
+    pub rotated_at: Option<DateTime<Utc>>,
+    pub client_id: Option<String>,
+    pub scope: Option<String>,
+    pub impersonator: Option<Uuid>,
+}
+
+impl Session {
+    /// Identifies the session to its owner. The key itself is a credential, a prefix of its digest
+    /// is not.
+    pub fn digest(&self) -> String {
+        token::hash(&self.key)[..12].to_owned()
+    }
+}
+
+#[juniper::graphql_object]
+impl Session {
+    fn id(&self) -> String {
+        self.digest()
+    }
+
+    fn ip(&self) -> Option<String> {
+        self.identity.ip.map(|ip| ip.to_string())
+    }
+
+    fn fingerprint(&self) -> Option<String> {
+        self.identity.fingerprint.to_owned()
+    }
+
+    fn expiry(&self) -> DateTime<Utc> {
+        self.expiry
+    }
+
+    fn created_at(&self) -> DateTime<Utc> {
+        self.created_at
+    }
+
+    fn updated_at(&self) -> Option<DateTime<Utc>> {
+        self.updated_at
+    }
+
+    /// OAuth client the session was issued to, if any
+    fn client_id(&self) -> Option<String> {
+        self.client_id.to_owned()
+    }
+
+    fn scope(&self) -> Option<String> {
+        self.scope.to_owned()
+    }
+
+    /// Staff account acting as the account, if the session is an impersonation
+    fn impersonator(&self) -> Option<Uuid> {
+        self.impersonator
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize, Default, Debug)]
@@ -0,0 +1,485 @@
+use crate::{
+    auth::Claims,
+    environment::Environment,
+    helpers::{cache, token},
+    model::session::Identity,
+    session::Session,
+    sql::{account::NewSession, oauth_client::OauthClient},
+};
+use chrono::{Duration, Utc};
+use ring::{constant_time, digest};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use uuid::Uuid;
+
+/// Scopes clients may be granted, `read` for queries and `write` for mutations.
+pub const SCOPES: [&str; 2] = ["read", "write"];
+/// Seconds an authorization code stays redeemable.
+const CODE_LIFETIME: usize = 60;
+
+/// Errors of RFC 6749, section 5.2.
+#[derive(Error, Debug)]
+pub enum OauthError {
+    #[error("invalid_request")]
+    InvalidRequest,
+    #[error("invalid_client")]
+    InvalidClient,
+    #[error("invalid_grant")]
+    InvalidGrant,
+    #[error("invalid_scope")]
+    InvalidScope,
+    #[error("unsupported_grant_type")]
+    UnsupportedGrantType,
+}
+
+pub struct Registration {
+    pub client_id: String,
+    pub client_secret: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AuthorizationRequest {
+    response_type: String,
+    client_id: String,
+    redirect_uri: Option<String>,
+    scope: Option<String>,
+    state: Option<String>,
+    code_challenge: String,
+    code_challenge_method: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Authorization {
+    redirect_uri: String,
+}
+
+/// An authorization code waiting to be exchanged, kept in Redis under its hash.
+#[derive(Serialize, Deserialize, Debug)]
+struct AuthorizationCode {
+    client_id: String,
+    account: Uuid,
+    redirect_uri: String,
+    scope: String,
+    code_challenge: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TokenRequest {
+    grant_type: String,
+    code: Option<String>,
+    redirect_uri: Option<String>,
+    code_verifier: Option<String>,
+    scope: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AccessToken {
+    access_token: String,
+    token_type: &'static str,
+    expires_in: i64,
+    scope: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TokenReference {
+    token: String,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+}
+
+#[derive(Serialize, Default, Debug)]
+pub struct Introspection {
+    active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sub: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exp: Option<i64>,
+}
+
+/// Checks that every scope of `requested` is among `allowed`, returning them normalized.
+pub fn scope(requested: &str, allowed: &str) -> Result<String, OauthError> {
+    let scopes = requested.split_whitespace().collect::<Vec<_>>();
+    if scopes.is_empty()
+        || !scopes
+            .iter()
+            .all(|scope| allowed.split(' ').any(|s| s == *scope))
+    {
+        return Err(OauthError::InvalidScope);
+    }
+
+    Ok(scopes.join(" "))
+}
+
+/// Registers a client owned by the account. Confidential clients get a secret, which is only
+/// returned here.
+pub async fn register(
+    env: &Environment,
+    session: &Session,
+    name: &str,
+    redirect_uri: &str,
+    requested_scope: &str,
+    confidential: bool,
+) -> anyhow::Result<Registration> {
+    let redirect = url::Url::parse(redirect_uri).or(Err(OauthError::InvalidRequest))?;
+    if redirect.fragment().is_some() {
+        return Err(OauthError::InvalidRequest.into());
+    }
+
+    let client_secret = if confidential {
+        Some(token::generate())
+    } else {
+        None
+    };
+    let client = OauthClient {
+        id: token::generate(),
+        account: session.account_id(),
+        name: name.to_owned(),
+        secret: client_secret.as_deref().map(token::hash),
+        redirect_uri: redirect_uri.to_owned(),
+        scope: scope(requested_scope, &SCOPES.join(" "))?,
+    };
+    crate::sql::oauth_client::create_client(env.database(), &client).await?;
+
+    Ok(Registration {
+        client_id: client.id,
+        client_secret,
+    })
+}
+
+/// Deletes a client of the account and revokes every token issued to it.
+pub async fn delete(env: &Environment, session: &Session, client_id: &str) -> anyhow::Result<bool> {
+    match crate::sql::oauth_client::get_client(env.database(), client_id).await? {
+        Some(client) if client.account == session.account_id() => (),
+        _ => return Ok(false),
+    }
+
+    let keys = crate::sql::session::invalidate_client_sessions(env.database(), client_id).await?;
+    let mut redis = env.redis().await?;
+    for key in &keys {
+        crate::session::purge(&mut redis, key).await?;
+    }
+
+    crate::sql::oauth_client::delete_client(env.database(), session.account_id(), client_id)
+        .await?;
+
+    Ok(true)
+}
+
+/// Grants the client access to the logged in account, after the user consented in the frontend.
+/// Returns the client's redirect URI carrying the authorization code.
+pub async fn authorize(
+    env: &Environment,
+    session: &Session,
+    req: AuthorizationRequest,
+) -> anyhow::Result<Authorization> {
+    session.require_first_party()?;
+    session.require_not_impersonating()?;
+
+    let client = crate::sql::oauth_client::get_client(env.database(), &req.client_id)
+        .await?
+        .ok_or(OauthError::InvalidClient)?;
+    if req
+        .redirect_uri
+        .map_or(false, |uri| uri != client.redirect_uri)
+    {
+        return Err(OauthError::InvalidRequest.into());
+    }
+    // PKCE is required of every client, confidential or not.
+    if req.response_type != "code" || req.code_challenge_method != "S256" {
+        return Err(OauthError::InvalidRequest.into());
+    }
+    let scope = scope(req.scope.as_deref().unwrap_or(&client.scope), &client.scope)?;
+
+    let code = token::generate();
+    cache::set_ex(
+        &mut env.redis().await?,
+        format!("oauth:code:{}", token::hash(&code)),
+        &AuthorizationCode {
+            client_id: client.id,
+            account: session.account_id(),
+            redirect_uri: client.redirect_uri.to_owned(),
+            scope,
+            code_challenge: req.code_challenge,
+        },
+        CODE_LIFETIME,
+    )
+    .await?;
+
+    let mut redirect_uri = url::Url::parse(&client.redirect_uri)?;
+    redirect_uri.query_pairs_mut().append_pair("code", &code);
+    if let Some(state) = req.state {
+        redirect_uri.query_pairs_mut().append_pair("state", &state);
+    }
+
+    Ok(Authorization {
+        redirect_uri: redirect_uri.into_string(),
+    })
+}
+
+/// Authenticates a client with HTTP Basic or the form parameters. Public clients have no secret
+/// and are identified by their id alone.
+async fn authenticate(
+    env: &Environment,
+    authorization: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+) -> anyhow::Result<OauthClient> {
+    let (client_id, client_secret) = credentials(authorization, client_id, client_secret);
+
+    let client = crate::sql::oauth_client::get_client(
+        env.database(),
+        &client_id.ok_or(OauthError::InvalidClient)?,
+    )
+    .await?
+    .ok_or(OauthError::InvalidClient)?;
+    verify_secret(&client, client_secret)?;
+
+    Ok(client)
+}
+
+/// Takes the client id and secret from HTTP Basic when present, otherwise from the form.
+fn credentials(
+    authorization: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+) -> (Option<String>, Option<String>) {
+    let basic = authorization
+        .as_deref()
+        .and_then(|header| header.strip_prefix("Basic "))
+        .and_then(|encoded| base64::decode(encoded).ok())
+        .and_then(|decoded| String::from_utf8(decoded).ok());
+    match basic {
+        Some(basic) => {
+            let mut parts = basic.splitn(2, ':');
+            (
+                parts.next().map(str::to_owned),
+                parts.next().map(str::to_owned),
+            )
+        }
+        None => (client_id, client_secret),
+    }
+}
+
+/// Confidential clients must present their secret, public clients must not present any.
+fn verify_secret(client: &OauthClient, secret: Option<String>) -> Result<(), OauthError> {
+    match (&client.secret, secret.filter(|secret| !secret.is_empty())) {
+        (Some(hash), Some(secret)) => {
+            constant_time::verify_slices_are_equal(hash.as_bytes(), token::hash(&secret).as_bytes())
+                .or(Err(OauthError::InvalidClient))
+        }
+        (None, None) => Ok(()),
+        _ => Err(OauthError::InvalidClient),
+    }
+}
+
+/// Checks the PKCE verifier against the S256 challenge the code was issued for.
+fn verify_pkce(verifier: &str, challenge: &str) -> bool {
+    let expected = base64::encode_config(
+        digest::digest(&digest::SHA256, verifier.as_bytes()),
+        base64::URL_SAFE_NO_PAD,
+    );
+
+    constant_time::verify_slices_are_equal(expected.as_bytes(), challenge.as_bytes()).is_ok()
+}
+
+/// Token endpoint for the authorization code and client credentials grants. Client credentials
+/// act on behalf of the account owning the client.
+pub async fn token(
+    env: &Environment,
+    authorization: Option<String>,
+    req: TokenRequest,
+) -> anyhow::Result<AccessToken> {
+    let client = authenticate(env, authorization, req.client_id, req.client_secret).await?;
+
+    match req.grant_type.as_str() {
+        "authorization_code" => {
+            let code = req.code.ok_or(OauthError::InvalidRequest)?;
+            let verifier = req.code_verifier.ok_or(OauthError::InvalidRequest)?;
+
+            let key = format!("oauth:code:{}", token::hash(&code));
+            let pending: AuthorizationCode = cache::take(&mut env.redis().await?, &key)
+                .await
+                .or(Err(OauthError::InvalidGrant))?;
+
+            if pending.client_id != client.id
+                || req
+                    .redirect_uri
+                    .map_or(false, |uri| uri != pending.redirect_uri)
+                || !verify_pkce(&verifier, &pending.code_challenge)
+            {
+                return Err(OauthError::InvalidGrant.into());
+            }
+
+            issue(env, &client, pending.account, &pending.scope).await
+        }
+        "client_credentials" => {
+            if client.secret.is_none() {
+                return Err(OauthError::InvalidClient.into());
+            }
+            let scope = scope(req.scope.as_deref().unwrap_or(&client.scope), &client.scope)?;
+
+            issue(env, &client, client.account, &scope).await
+        }
+        _ => Err(OauthError::UnsupportedGrantType.into()),
+    }
+}
+
+/// Access tokens are sessions carrying the client and its scopes, without a usable refresh token.
+async fn issue(
+    env: &Environment,
+    client: &OauthClient,
+    account: Uuid,
+    scope: &str,
+) -> anyhow::Result<AccessToken> {
+    let claims = Claims::generate();
+    let session = claims.session();
+    let lifetime = env.oauth_access_token_lifetime();
+    let expiry = Utc::now() + Duration::seconds(lifetime);
+
+    crate::sql::account::create_session(
+        env.database(),
+        NewSession {
+            key: &session,
+            csrf: &claims.csrf(),
+            family: &session,
+            refresh_token: &token::hash(&token::generate()),
+            expiry,
+            refresh_expiry: expiry,
+            client_id: Some(&client.id),
+            scope: Some(scope),
+            impersonator: None,
+        },
+        account,
+        Identity::default(),
+    )
+    .await?;
+
+    Ok(AccessToken {
+        access_token: env.jwt().encode(claims, expiry)?,
+        token_type: "Bearer",
+        expires_in: lifetime,
+        scope: scope.to_owned(),
+    })
+}
+
+/// Token introspection (RFC 7662). Like `revoke`, tokens of other clients are reported inactive.
+pub async fn introspect(
+    env: &Environment,
+    authorization: Option<String>,
+    req: TokenReference,
+) -> anyhow::Result<Introspection> {
+    let client = authenticate(env, authorization, req.client_id, req.client_secret).await?;
+
+    let session = match env.jwt().decode(&req.token) {
+        Ok(claims) => crate::sql::session::get_session(env.database(), &claims.session()).await?,
+        Err(_) => None,
+    };
+
+    match session {
+        Some(session) if session.client_id.as_deref() == Some(client.id.as_str()) => {
+            Ok(Introspection {
+                active: true,
+                scope: session.scope,
+                client_id: session.client_id,
+                sub: Some(session.account.to_string()),
+                exp: Some(session.expiry.timestamp()),
+            })
+        }
+        _ => Ok(Introspection::default()),
+    }
+}
+
+/// Token revocation (RFC 7009). Unknown tokens and tokens of other clients are ignored.
+pub async fn revoke(
+    env: &Environment,
+    authorization: Option<String>,
+    req: TokenReference,
+) -> anyhow::Result<()> {
+    let client = authenticate(env, authorization, req.client_id, req.client_secret).await?;
+
+    if let Ok(claims) = env.jwt().decode(&req.token) {
+        if let Some(session) =
+            crate::sql::session::get_session(env.database(), &claims.session()).await?
+        {
+            if session.client_id.as_deref() == Some(client.id.as_str()) {
+                crate::session::revoke(env, &session.key).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(secret: Option<&str>) -> OauthClient {
+        OauthClient {
+            id: "client".to_owned(),
+            account: Uuid::new_v4(),
+            name: "Client".to_owned(),
+            secret: secret.map(token::hash),
+            redirect_uri: "https://client.example/callback".to_owned(),
+            scope: "read write".to_owned(),
+        }
+    }
+
+    #[test]
+    fn scope_normalizes_granted_scopes() {
+        assert_eq!(scope(" write  read ", "read write").unwrap(), "write read");
+    }
+
+    #[test]
+    fn scope_rejects_scopes_beyond_the_client() {
+        assert!(scope("read admin", "read write").is_err());
+        assert!(scope("write", "read").is_err());
+        assert!(scope("  ", "read write").is_err());
+    }
+
+    #[test]
+    fn basic_credentials_take_precedence() {
+        let basic = format!("Basic {}", base64::encode("client:secret"));
+        assert_eq!(
+            credentials(Some(basic), Some("other".to_owned()), None),
+            (Some("client".to_owned()), Some("secret".to_owned()))
+        );
+        assert_eq!(
+            credentials(None, Some("client".to_owned()), None),
+            (Some("client".to_owned()), None)
+        );
+    }
+
+    #[test]
+    fn confidential_clients_need_their_secret() {
+        let client = client(Some("secret"));
+        assert!(verify_secret(&client, Some("secret".to_owned())).is_ok());
+        assert!(verify_secret(&client, Some("guessed".to_owned())).is_err());
+        assert!(verify_secret(&client, Some(String::new())).is_err());
+        assert!(verify_secret(&client, None).is_err());
+    }
+
+    #[test]
+    fn public_clients_present_no_secret() {
+        let client = client(None);
+        assert!(verify_secret(&client, None).is_ok());
+        assert!(verify_secret(&client, Some(String::new())).is_ok());
+        assert!(verify_secret(&client, Some("secret".to_owned())).is_err());
+    }
+
+    #[test]
+    fn pkce_accepts_only_the_verifier_of_the_challenge() {
+        // The example of RFC 7636, appendix B.
+        let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+        let challenge = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+        assert!(verify_pkce(verifier, challenge));
+        assert!(!verify_pkce("guessed", challenge));
+        assert!(!verify_pkce(verifier, verifier));
+    }
+}
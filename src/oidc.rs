@@ -0,0 +1,625 @@
+use crate::{
+    auth::AuthError,
+    environment::{oidc::Provider, Environment},
+    helpers::{cache, token},
+};
+use biscuit::{jwk::JWKSet, ClaimPresenceOptions, Presence, Validation, ValidationOptions};
+use http_api_problem::HttpApiProblem as Problem;
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use warp::http;
+
+/// Seconds the user may spend at the provider before the login is abandoned.
+const LOGIN_LIFETIME: usize = 600;
+/// Seconds discovery documents and signing keys of a provider are cached.
+const DISCOVERY_LIFETIME: usize = 3600;
+/// Holds the digest of the login's `state`, so a callback is only accepted in the browser that
+/// started the login and an attacker can't log a victim into the attacker's account.
+pub const STATE_COOKIE: &str = "oidc_state";
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+/// A login waiting for the provider to redirect back, kept in Redis under its `state`.
+#[derive(Serialize, Deserialize, Debug)]
+struct PendingLogin {
+    provider: String,
+    nonce: String,
+    verifier: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Callback {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct IdClaims {
+    nonce: Option<String>,
+    email: Option<String>,
+    email_verified: Option<bool>,
+}
+
+fn provider<'a>(env: &'a Environment, name: &str) -> anyhow::Result<&'a Provider> {
+    env.oidc().provider(name).ok_or_else(|| {
+        Problem::new("Unknown provider.")
+            .set_status(http::StatusCode::NOT_FOUND)
+            .set_detail(format!(
+                "No identity provider named {} is configured.",
+                name
+            ))
+            .into()
+    })
+}
+
+async fn discover(env: &Environment, provider: &Provider) -> anyhow::Result<Discovery> {
+    cache::get_or_create(
+        &mut env.redis().await?,
+        format!("oidc:discovery:{}", provider.name),
+        || async {
+            let discovery = fetch_discovery(env.oidc().client(), provider).await?;
+            Ok((discovery, DISCOVERY_LIFETIME))
+        },
+    )
+    .await
+}
+
+/// A document announcing any other issuer than the configured one is refused, as OpenID Connect
+/// Discovery requires.
+async fn fetch_discovery(
+    client: &reqwest::Client,
+    provider: &Provider,
+) -> anyhow::Result<Discovery> {
+    let discovery = client
+        .get(&format!(
+            "{}/.well-known/openid-configuration",
+            provider.issuer.trim_end_matches('/')
+        ))
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<Discovery>()
+        .await?;
+
+    if discovery.issuer != provider.issuer {
+        return Err(anyhow::anyhow!(
+            "oidc provider {} announced issuer {}",
+            provider.name,
+            discovery.issuer
+        ));
+    }
+
+    Ok(discovery)
+}
+
+/// Signing keys are cached as the raw JSON document, JWKs don't survive bincode.
+async fn jwks(
+    env: &Environment,
+    provider: &Provider,
+    discovery: &Discovery,
+) -> anyhow::Result<JWKSet<biscuit::Empty>> {
+    let jwks: String = cache::get_or_create(
+        &mut env.redis().await?,
+        format!("oidc:jwks:{}", provider.name),
+        || async {
+            let jwks = fetch_jwks(env.oidc().client(), discovery).await?;
+            Ok((jwks, DISCOVERY_LIFETIME))
+        },
+    )
+    .await?;
+
+    Ok(serde_json::from_str(&jwks)?)
+}
+
+async fn fetch_jwks(client: &reqwest::Client, discovery: &Discovery) -> anyhow::Result<String> {
+    Ok(client
+        .get(&discovery.jwks_uri)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?)
+}
+
+/// Sets the cookie binding a login to the browser, or clears it when `state` is empty.
+pub fn state_cookie(state: &str) -> String {
+    let (value, max_age) = if state.is_empty() {
+        (String::new(), 0)
+    } else {
+        (token::hash(state), LOGIN_LIFETIME)
+    };
+
+    format!(
+        "{}={}; Max-Age={}; Path=/auth/oidc; HttpOnly; SameSite=Lax",
+        STATE_COOKIE, value, max_age
+    )
+}
+
+fn check_state(state: &str, cookie: Option<&str>) -> Result<(), AuthError> {
+    match cookie {
+        Some(cookie) if cookie == token::hash(state) => Ok(()),
+        _ => Err(AuthError::InvalidCredentials),
+    }
+}
+
+/// Returns the authorization URL to send the browser to, using PKCE with S256, and the `state`
+/// for `state_cookie`.
+pub async fn start(env: &Environment, name: &str) -> anyhow::Result<(String, String)> {
+    let provider = provider(env, name)?;
+    let discovery = discover(env, provider).await?;
+
+    let state = token::generate();
+    let pending = PendingLogin {
+        provider: provider.name.to_owned(),
+        nonce: token::generate(),
+        verifier: token::generate(),
+    };
+    let challenge = base64::encode_config(
+        digest::digest(&digest::SHA256, pending.verifier.as_bytes()),
+        base64::URL_SAFE_NO_PAD,
+    );
+
+    let mut url = url::Url::parse(&discovery.authorization_endpoint)?;
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &provider.client_id)
+        .append_pair("redirect_uri", &env.oidc().redirect_uri(provider))
+        .append_pair("scope", "openid email profile")
+        .append_pair("state", &state)
+        .append_pair("nonce", &pending.nonce)
+        .append_pair("code_challenge", &challenge)
+        .append_pair("code_challenge_method", "S256");
+
+    cache::set_ex(
+        &mut env.redis().await?,
+        format!("oidc:{}", token::hash(&state)),
+        &pending,
+        LOGIN_LIFETIME,
+    )
+    .await?;
+
+    Ok((url.into_string(), state))
+}
+
+/// Exchanges the authorization code and returns the account the identity belongs to, linking or
+/// creating one on first use. `cookie` is the `STATE_COOKIE` the browser sent along.
+pub async fn callback(
+    env: &Environment,
+    name: &str,
+    callback: Callback,
+    cookie: Option<&str>,
+) -> anyhow::Result<Uuid> {
+    let provider = provider(env, name)?;
+
+    if let Some(error) = callback.error {
+        tracing::warn!("oidc provider {} returned {}", provider.name, error);
+        return Err(AuthError::InvalidCredentials.into());
+    }
+    let (code, state) = match (callback.code, callback.state) {
+        (Some(code), Some(state)) => (code, state),
+        _ => return Err(AuthError::InvalidCredentials.into()),
+    };
+    check_state(&state, cookie)?;
+
+    let key = format!("oidc:{}", token::hash(&state));
+    let mut redis = env.redis().await?;
+    let pending: PendingLogin = cache::take(&mut redis, &key)
+        .await
+        .or(Err(AuthError::InvalidCredentials))?;
+    if pending.provider != provider.name {
+        return Err(AuthError::InvalidCredentials.into());
+    }
+
+    let discovery = discover(env, provider).await?;
+    let id_token = exchange(
+        env.oidc().client(),
+        provider,
+        &discovery,
+        &env.oidc().redirect_uri(provider),
+        &code,
+        &pending.verifier,
+    )
+    .await?;
+    let (subject, claims) = validate(
+        provider,
+        &jwks(env, provider, &discovery).await?,
+        &id_token,
+        &pending.nonce,
+    )?;
+
+    if let Some(account) =
+        crate::sql::linked_identity::get_linked_account(env.database(), &provider.name, &subject)
+            .await?
+    {
+        return Ok(account);
+    }
+
+    let email = claims.email.ok_or(AuthError::InvalidCredentials)?;
+    let email_verified = claims.email_verified.unwrap_or(false);
+    let account = provision(env, provider, &email, email_verified).await?;
+
+    crate::sql::linked_identity::link_identity(
+        env.database(),
+        &provider.name,
+        &subject,
+        account,
+        Some(&email),
+    )
+    .await?;
+
+    Ok(account)
+}
+
+/// Redeems the authorization code with the PKCE verifier, returning the ID token.
+async fn exchange(
+    client: &reqwest::Client,
+    provider: &Provider,
+    discovery: &Discovery,
+    redirect_uri: &str,
+    code: &str,
+    verifier: &str,
+) -> anyhow::Result<String> {
+    let response = client
+        .post(&discovery.token_endpoint)
+        .form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", redirect_uri),
+            ("client_id", provider.client_id.as_str()),
+            ("client_secret", provider.client_secret.as_str()),
+            ("code_verifier", verifier),
+        ])
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        tracing::warn!(
+            "oidc provider {} rejected the code exchange with {}",
+            provider.name,
+            response.status()
+        );
+        return Err(AuthError::InvalidCredentials.into());
+    }
+
+    Ok(response.json::<TokenResponse>().await?.id_token)
+}
+
+/// Checks the ID token was signed by the provider for this client and this login, returning its
+/// subject. The issuer is compared with the configured one, never with what the provider claims.
+fn validate(
+    provider: &Provider,
+    jwks: &JWKSet<biscuit::Empty>,
+    id_token: &str,
+    nonce: &str,
+) -> anyhow::Result<(String, IdClaims)> {
+    let token = biscuit::JWT::<IdClaims, biscuit::Empty>::new_encoded(id_token)
+        .decode_with_jwks(jwks, None)?;
+    token.validate(ValidationOptions {
+        claim_presence_options: ClaimPresenceOptions {
+            issued_at: Presence::Required,
+            expiry: Presence::Required,
+            issuer: Presence::Required,
+            audience: Presence::Required,
+            subject: Presence::Required,
+            ..Default::default()
+        },
+        issuer: Validation::Validate(provider.issuer.to_owned()),
+        audience: Validation::Validate(provider.client_id.to_owned()),
+        ..Default::default()
+    })?;
+    let claims = token.payload()?;
+    if claims.private.nonce.as_deref() != Some(nonce) {
+        return Err(AuthError::InvalidCredentials.into());
+    }
+    let subject = claims
+        .registered
+        .subject
+        .to_owned()
+        .ok_or(AuthError::InvalidCredentials)?;
+
+    Ok((subject, claims.private.to_owned()))
+}
+
+/// Only a trusted provider vouching for the address may log into an existing account of it.
+/// Otherwise anyone able to register the address at the provider could take the account over.
+fn may_link(provider: &Provider, email_verified: bool) -> bool {
+    provider.trusted && email_verified
+}
+
+/// Links to the account of a verified email address if the provider is trusted with it, or creates
+/// an account without a usable password.
+async fn provision(
+    env: &Environment,
+    provider: &Provider,
+    email: &str,
+    email_verified: bool,
+) -> anyhow::Result<Uuid> {
+    if let Some(account) =
+        crate::sql::account::get_account_id_password_by_email(env.database(), email).await?
+    {
+        if !may_link(provider, email_verified) {
+            return Err(Problem::new("Account exists.")
+                .set_status(http::StatusCode::CONFLICT)
+                .set_detail("An account with this email address exists already, log in with it.")
+                .into());
+        }
+        return Ok(account.id);
+    }
+
+    let id = Uuid::new_v4();
+    let password = env
+        .argon()
+        .hasher()
+        .with_password(token::generate())
+        .hash()
+        .or(Err(AuthError::ArgonError))?;
+    crate::sql::account::create_account(env.database(), id, email, &password).await?;
+
+    if email_verified {
+        crate::sql::account::mark_email_verified(env.database(), id, email).await?;
+    } else if let Err(err) = crate::verification::send(env, id, email).await {
+        tracing::error!("could not send verification mail: {:#}", err);
+    }
+
+    Ok(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use biscuit::{jwa::SignatureAlgorithm, jws::Secret, ClaimsSet, RegisteredClaims};
+    use chrono::{Duration, Utc};
+    use ring::signature::{KeyPair, RsaKeyPair};
+    use serde_json::json;
+    use std::{collections::HashMap, sync::Arc};
+    use warp::Filter;
+
+    /// PKCS#8 RSA key the stub issuer signs with, generated for these tests only.
+    const KEY: &str = concat!(
+        "MIIEvAIBADANBgkqhkiG9w0BAQEFAASCBKYwggSiAgEAAoIBAQCeynnpley+fiEXYXKCsC9KucTFxz+lHO7s3ZQP",
+        "EzL0EjMlaKKcGqDY2IFDSPgJ0WFbx2EFjgea5IY0vIR0Esvku84GRhwK8s4wNQ1mDxMYNwVjtxX9DoMXObsOxIxj",
+        "ovjHLiqNyz2gTQfJ9YP0NUCujtMjDzpvJpzLwENMlUSRnljY7w31D42GcOmbSS/loMJ7Vx23IgiE4/rn+s6gP9LM",
+        "XcHhiMCUNHoOsJEQrVM67iu4yUy1dD5nSfkm9ymdw5ZAWSJARlkMr8/opuHoPPX78jPCAdOLmSuj2Pu6pp9rdkgz",
+        "7T08G2JLsxIC/iyw44KtiwwjxxCbmxMwrcyMUal3AgMBAAECggEADg8KZC5fjsetnluEvyIvAOqzPt+GBW339ji+",
+        "Y+dLQTJeg0ot7qxDRjx4LvQS7quYqkuPJ3+MfmylOGriDA5t+iTQK0LVA/mJwwx8NzQ2eJP5tWGZLMjpvYpHwxlb",
+        "ywzuzpO9aMU9AfUcyQckiHnLVEK8JtDkvfQpOOqFpHSPl/5m72uclXCklmtdOIiNSL3RKlv+A/0IYXqbxzuOjSE2",
+        "9DtfCIDGdC6iB8VmLpyo97EZCv9Gvu0mHtRbKCSf1bF4KhQzp74qO/4ZGtzw/LU77qsNZv/lpHHuD+Du1PuiRZxH",
+        "3xL3w2lmMdsjb6tKP51GrkqUSEfAHKUPigkhQJgooQKBgQDMwbfPiInNnh9uLwgUw5jArjFFcZECO9cSuXuFrkag",
+        "H+E9QurPRnLuInmmHgwOUaRx9J0DynA+UaPUrL1lwLu/KHKUxy1tQ7BOemXxw5uSaNgLYLzmb6IAaemDxhJ2f1jw",
+        "UTE3I5KEi+n66fyU81lUtc5J9ysx1CKD9BoD/as2xQKBgQDGh9aIiKttNzTWfYqnGBUNjAv03LWlLo14G2Vwun+0",
+        "0OEtuh7siChoGOyRwURao0+xiceddcSQIJ9iSyaZuVMLR8ff7SPYmE+CBgp7vE3Z2t4v6c5yBJVTB9wc4OF9iroY",
+        "zixHvy9kJySpND15wIaMeTeUezYGVAmU43YYiCoDCwKBgCCmGvsamG6S8gwNr8ovziGbJPwfubGTfR3dOZb9TQGn",
+        "U7EDBJH8d8ME+ETGscFuvzv0dzztgKxPNEJrMOAStV0rw+l5QQMVMf2xgqPkPuA/m4Nk73oG/tv2B2gdo0AF2lan",
+        "e6OBJpHABZUezkmp6+qX+sbMhLSzULWdrLBKOIzJAoGAHh06wIkin7VxEVwHrSLtBpoIskLMVtsB4IcfUwMnHfFV",
+        "Rs9lo3Ff17J4J//N84W0L/T7T3our8ITCyDlIQpIe3yO5/ubpV4tKUbrTiuZD4Owkkn/47GoSOj3TXYbHiTt55dW",
+        "hieM878wQ25GKPK4NhSpBp/N6RXUQj5iioQVA1MCgYB7Kez0IEmiHpSp6g9Igq5PWgZGqiRyxIiINDutCXR4U0hE",
+        "Po4XmN2iSK/JNXcwtxRnOnItgXnerGSzatoVoSCbkGLiCZwlMfEBv/rwq+g7S7pvC0alVDozquE8RgAMB0INtuLu",
+        "1ZDZ8ltXRQgUAB5S5E9LybDeY3KHKjGF3Vbzaw==",
+    );
+    const VERIFIER: &str = "verifier";
+    const NONCE: &str = "nonce";
+
+    /// What the stub puts into discovery and the ID token, `None` stands for its own URL, this
+    /// client and the expected nonce.
+    #[derive(Clone, Default)]
+    struct Stub {
+        announced_issuer: Option<String>,
+        issuer: Option<String>,
+        audience: Option<String>,
+        nonce: Option<String>,
+    }
+
+    impl Stub {
+        /// Serves discovery, keys and a token endpoint on an ephemeral port, returning the issuer.
+        fn serve(self) -> String {
+            let key = Arc::new(RsaKeyPair::from_pkcs8(&base64::decode(KEY).unwrap()).unwrap());
+            let encode = |bytes: &[u8]| base64::encode_config(bytes, base64::URL_SAFE_NO_PAD);
+            let jwks = json!({ "keys": [{
+                "kty": "RSA",
+                "use": "sig",
+                "alg": "RS256",
+                "kid": "stub",
+                "n": encode(key.public_key().modulus().big_endian_without_leading_zero()),
+                "e": encode(key.public_key().exponent().big_endian_without_leading_zero()),
+            }]});
+
+            let stub = self.clone();
+            let discovery = warp::path!(".well-known" / "openid-configuration")
+                .and(warp::header::<String>("host"))
+                .map(move |host: String| {
+                    let url = format!("http://{}", host);
+                    warp::reply::json(&Discovery {
+                        issuer: stub
+                            .announced_issuer
+                            .to_owned()
+                            .unwrap_or_else(|| url.clone()),
+                        authorization_endpoint: format!("{}/authorize", url),
+                        token_endpoint: format!("{}/token", url),
+                        jwks_uri: format!("{}/jwks", url),
+                    })
+                });
+            let keys = warp::path!("jwks").map(move || warp::reply::json(&jwks));
+            let stub = self;
+            let token = warp::path!("token")
+                .and(warp::post())
+                .and(warp::header::<String>("host"))
+                .and(warp::body::form())
+                .map(move |host: String, form: HashMap<String, String>| {
+                    if form.get("code_verifier").map(String::as_str) != Some(VERIFIER) {
+                        return warp::reply::with_status(
+                            warp::reply::json(&json!({ "error": "invalid_grant" })),
+                            http::StatusCode::BAD_REQUEST,
+                        );
+                    }
+                    let now = Utc::now();
+                    let claims = ClaimsSet {
+                        registered: RegisteredClaims {
+                            issuer: Some(
+                                stub.issuer
+                                    .to_owned()
+                                    .unwrap_or_else(|| format!("http://{}", host)),
+                            ),
+                            subject: Some("alice".to_owned()),
+                            audience: Some(biscuit::SingleOrMultiple::Single(
+                                stub.audience
+                                    .to_owned()
+                                    .unwrap_or_else(|| "client".to_owned()),
+                            )),
+                            expiry: Some((now + Duration::minutes(5)).into()),
+                            issued_at: Some(now.into()),
+                            ..Default::default()
+                        },
+                        private: IdClaims {
+                            nonce: Some(stub.nonce.to_owned().unwrap_or_else(|| NONCE.to_owned())),
+                            email: Some("alice@example.com".to_owned()),
+                            email_verified: Some(true),
+                        },
+                    };
+                    let id_token = biscuit::JWT::<IdClaims, biscuit::Empty>::new_decoded(
+                        From::from(biscuit::jws::RegisteredHeader {
+                            algorithm: SignatureAlgorithm::RS256,
+                            key_id: Some("stub".to_owned()),
+                            ..Default::default()
+                        }),
+                        claims,
+                    )
+                    .into_encoded(&Secret::RsaKeyPair(Arc::clone(&key)))
+                    .unwrap()
+                    .unwrap_encoded()
+                    .to_string();
+
+                    warp::reply::with_status(
+                        warp::reply::json(&json!({ "id_token": id_token })),
+                        http::StatusCode::OK,
+                    )
+                });
+
+            let (addr, server) =
+                warp::serve(discovery.or(keys).or(token)).bind_ephemeral(([127, 0, 0, 1], 0));
+            tokio::spawn(server);
+
+            format!("http://{}", addr)
+        }
+    }
+
+    fn provider(issuer: String) -> Provider {
+        Provider {
+            name: "stub".to_owned(),
+            client_id: "client".to_owned(),
+            client_secret: "secret".to_owned(),
+            issuer,
+            trusted: false,
+        }
+    }
+
+    /// Runs the callback against the provider without Redis or a database, returning the subject.
+    async fn login(provider: &Provider, verifier: &str) -> anyhow::Result<String> {
+        let client = reqwest::Client::new();
+        let discovery = fetch_discovery(&client, provider).await?;
+        let id_token = exchange(
+            &client,
+            provider,
+            &discovery,
+            "http://localhost/auth/oidc/stub/callback",
+            "code",
+            verifier,
+        )
+        .await?;
+        let jwks = serde_json::from_str(&fetch_jwks(&client, &discovery).await?)?;
+        let (subject, _) = validate(provider, &jwks, &id_token, NONCE)?;
+
+        Ok(subject)
+    }
+
+    #[tokio::test]
+    async fn accepts_tokens_of_the_configured_issuer() {
+        let provider = provider(Stub::default().serve());
+        assert_eq!(login(&provider, VERIFIER).await.unwrap(), "alice");
+    }
+
+    #[tokio::test]
+    async fn rejects_tokens_of_another_issuer() {
+        let stub = Stub {
+            issuer: Some("https://attacker.example".to_owned()),
+            ..Default::default()
+        };
+        assert!(login(&provider(stub.serve()), VERIFIER).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_discovery_announcing_another_issuer() {
+        let stub = Stub {
+            announced_issuer: Some("https://attacker.example".to_owned()),
+            issuer: Some("https://attacker.example".to_owned()),
+            ..Default::default()
+        };
+        assert!(login(&provider(stub.serve()), VERIFIER).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_tokens_for_another_client() {
+        let stub = Stub {
+            audience: Some("other".to_owned()),
+            ..Default::default()
+        };
+        assert!(login(&provider(stub.serve()), VERIFIER).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_tokens_of_another_login() {
+        let stub = Stub {
+            nonce: Some("other".to_owned()),
+            ..Default::default()
+        };
+        assert!(login(&provider(stub.serve()), VERIFIER).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_codes_without_the_verifier() {
+        let provider = provider(Stub::default().serve());
+        assert!(login(&provider, "guessed").await.is_err());
+    }
+
+    #[test]
+    fn accepts_the_state_of_this_browser() {
+        let cookie = token::hash("state");
+        assert!(check_state("state", Some(&cookie)).is_ok());
+    }
+
+    #[test]
+    fn rejects_states_started_in_another_browser() {
+        let cookie = token::hash("attacker");
+        assert!(check_state("state", Some(&cookie)).is_err());
+        assert!(check_state("state", None).is_err());
+    }
+
+    #[test]
+    fn state_cookie_holds_only_the_digest() {
+        let cookie = state_cookie("state");
+        assert!(cookie.starts_with(&format!("{}={};", STATE_COOKIE, token::hash("state"))));
+        assert!(cookie.contains("HttpOnly"));
+        assert!(state_cookie("").contains("Max-Age=0"));
+    }
+
+    #[test]
+    fn links_existing_accounts_only_for_trusted_providers() {
+        let mut provider = provider("http://localhost".to_owned());
+        assert!(!may_link(&provider, true));
+
+        provider.trusted = true;
+        assert!(may_link(&provider, true));
+        assert!(!may_link(&provider, false));
+    }
+}
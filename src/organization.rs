@@ -0,0 +1,344 @@
+use crate::{
+    auth::AuthError,
+    environment::{Environment, Mail},
+    helpers::token,
+    model,
+    session::{Organization, Session},
+    sql::organization::NewInvitation,
+};
+use chrono::{Duration, Utc};
+use http_api_problem::HttpApiProblem as Problem;
+use sqlx::postgres::PgConnection;
+use uuid::Uuid;
+use warp::http;
+
+/// Roles within an organization, from most to least privileged.
+pub const ROLES: [&str; 3] = ["owner", "admin", "member"];
+/// Roles allowed to invite, remove and promote members. Only owners may touch owners.
+pub const MANAGERS: [&str; 2] = ["owner", "admin"];
+
+fn role(role: &str) -> anyhow::Result<&str> {
+    if ROLES.contains(&role) {
+        Ok(role)
+    } else {
+        Err(Problem::new("Invalid role.")
+            .set_status(http::StatusCode::BAD_REQUEST)
+            .set_detail(format!("Roles are one of {}.", ROLES.join(", ")))
+            .into())
+    }
+}
+
+fn conflict(detail: &str) -> anyhow::Error {
+    Problem::new("Conflict.")
+        .set_status(http::StatusCode::CONFLICT)
+        .set_detail(detail.to_owned())
+        .into()
+}
+
+/// Creates an organization owned by the account and makes it the active one.
+pub async fn create(
+    env: &Environment,
+    session: &Session,
+    name: &str,
+) -> anyhow::Result<model::Organization> {
+    let id = Uuid::new_v4();
+    crate::sql::organization::create_organization(env.database(), id, name, session.account_id())
+        .await?;
+    session.set_organization(Some(id)).await?;
+
+    crate::sql::organization::get_organization(
+        &mut *env.database().acquire().await?,
+        id,
+        session.account_id(),
+    )
+    .await?
+    .ok_or_else(|| anyhow::anyhow!("organization {} vanished", id))
+}
+
+/// Switches the session to another organization of the account, or to none.
+pub async fn switch(
+    env: &Environment,
+    session: &Session,
+    organization: Option<Uuid>,
+) -> anyhow::Result<Option<model::Organization>> {
+    let organization = match organization {
+        Some(id) => Some(
+            crate::sql::organization::get_organization(
+                &mut *env.database().acquire().await?,
+                id,
+                session.account_id(),
+            )
+            .await?
+            .ok_or(AuthError::Forbidden)?,
+        ),
+        None => None,
+    };
+    session
+        .set_organization(organization.as_ref().map(|organization| organization.id))
+        .await?;
+
+    Ok(organization)
+}
+
+pub async fn invite(
+    env: &Environment,
+    session: &Session,
+    organization: &Organization,
+    email: &str,
+    requested_role: &str,
+) -> anyhow::Result<()> {
+    let role = role(requested_role)?;
+    if role == "owner" && organization.role != "owner" {
+        return Err(AuthError::Forbidden.into());
+    }
+    if crate::sql::organization::has_member_with_email(env.database(), organization.id, email)
+        .await?
+    {
+        return Err(conflict("The address belongs to a member already."));
+    }
+
+    let invitation_token = token::generate();
+    let expiry = Utc::now() + Duration::seconds(env.invitation_lifetime());
+    crate::sql::organization::create_invitation(
+        env.database(),
+        NewInvitation {
+            id: Uuid::new_v4(),
+            token: &token::hash(&invitation_token),
+            organization: organization.id,
+            email,
+            role,
+            invited_by: session.account_id(),
+            expiry,
+        },
+    )
+    .await?;
+
+    let name = crate::sql::organization::get_organization(
+        &mut *env.database().acquire().await?,
+        organization.id,
+        session.account_id(),
+    )
+    .await?
+    .map(|organization| organization.name)
+    .unwrap_or_default();
+    let mut link = url::Url::parse(env.invitation_url())?;
+    link.query_pairs_mut()
+        .append_pair("token", &invitation_token);
+
+    env.mailer()
+        .send(Mail {
+            to: email.to_owned(),
+            subject: format!("You were invited to {}", name),
+            body: format!(
+                "Open the following link to join {}, the invitation expires at {}.\n\n{}",
+                name,
+                expiry.to_rfc2822(),
+                link
+            ),
+        })
+        .await
+}
+
+/// Joins the organization of the invitation, which must have been sent to the account's verified
+/// address. The invitation is only used up once the membership exists.
+pub async fn accept(
+    env: &Environment,
+    session: &Session,
+    invitation_token: &str,
+) -> anyhow::Result<model::Organization> {
+    let mut transaction = env.database().begin().await?;
+    let invitation = crate::sql::organization::take_invitation(
+        &mut transaction,
+        &token::hash(invitation_token),
+        session.account_id(),
+    )
+    .await?
+    .ok_or(AuthError::InvalidCredentials)?;
+
+    crate::sql::organization::add_membership(
+        &mut transaction,
+        invitation.organization,
+        session.account_id(),
+        &invitation.role,
+    )
+    .await?;
+    transaction.commit().await?;
+
+    crate::sql::organization::get_organization(
+        &mut *env.database().acquire().await?,
+        invitation.organization,
+        session.account_id(),
+    )
+    .await?
+    .ok_or_else(|| anyhow::anyhow!("organization {} vanished", invitation.organization))
+}
+
+/// Anyone holding the token may decline, no account is needed for that.
+pub async fn decline(env: &Environment, invitation_token: &str) -> anyhow::Result<bool> {
+    Ok(
+        crate::sql::organization::decline_invitation(
+            env.database(),
+            &token::hash(invitation_token),
+        )
+        .await?
+            > 0,
+    )
+}
+
+pub async fn revoke_invitation(
+    env: &Environment,
+    organization: &Organization,
+    id: Uuid,
+) -> anyhow::Result<bool> {
+    Ok(crate::sql::organization::delete_invitation(env.database(), organization.id, id).await? > 0)
+}
+
+/// Owners can only be changed by owners, and the last one can't step down. The owners stay locked
+/// until the transaction ends, so two of them can't step down at once.
+async fn check_owner_change(
+    connection: &mut PgConnection,
+    organization: &Organization,
+    current: &str,
+) -> anyhow::Result<()> {
+    if current == "owner" {
+        if organization.role != "owner" {
+            return Err(AuthError::Forbidden.into());
+        }
+        if crate::sql::organization::count_owners(connection, organization.id).await? <= 1 {
+            return Err(conflict("An organization needs at least one owner."));
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn change_role(
+    env: &Environment,
+    organization: &Organization,
+    account: Uuid,
+    requested_role: &str,
+) -> anyhow::Result<bool> {
+    let role = role(requested_role)?;
+    if role == "owner" && organization.role != "owner" {
+        return Err(AuthError::Forbidden.into());
+    }
+    let mut transaction = env.database().begin().await?;
+    let current = match crate::sql::organization::get_membership_role(
+        &mut transaction,
+        organization.id,
+        account,
+    )
+    .await?
+    {
+        Some(current) if current == role => return Ok(true),
+        Some(current) => current,
+        None => return Ok(false),
+    };
+    check_owner_change(&mut transaction, organization, &current).await?;
+
+    let updated = crate::sql::organization::update_membership_role(
+        &mut transaction,
+        organization.id,
+        account,
+        role,
+    )
+    .await?;
+    transaction.commit().await?;
+
+    Ok(updated > 0)
+}
+
+/// Removes a member, managers may remove anyone and every member may leave.
+pub async fn remove_member(
+    env: &Environment,
+    session: &Session,
+    organization: &Organization,
+    account: Uuid,
+) -> anyhow::Result<bool> {
+    if account != session.account_id() && !MANAGERS.contains(&organization.role.as_str()) {
+        return Err(AuthError::Forbidden.into());
+    }
+    let mut transaction = env.database().begin().await?;
+    let current = match crate::sql::organization::get_membership_role(
+        &mut transaction,
+        organization.id,
+        account,
+    )
+    .await?
+    {
+        Some(current) => current,
+        None => return Ok(false),
+    };
+    check_owner_change(&mut transaction, organization, &current).await?;
+
+    let deleted =
+        crate::sql::organization::delete_membership(&mut transaction, organization.id, account)
+            .await?;
+    transaction.commit().await?;
+
+    Ok(deleted > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::{postgres::PgPool, query_unchecked};
+
+    /// Commits an organization with a single owner to `DATABASE_URL` and asks whether `role` may
+    /// change that owner.
+    async fn owner_change(role: &str) -> Result<(), String> {
+        dotenv::dotenv().ok();
+        let pool = PgPool::new(&std::env::var("DATABASE_URL").unwrap())
+            .await
+            .unwrap();
+        let (organization, owner) = (Uuid::new_v4(), Uuid::new_v4());
+        query_unchecked!(
+            "INSERT INTO accounts (id, email, password) VALUES ($1, $2, '')",
+            owner,
+            format!("{}@organization.test", owner)
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        crate::sql::organization::create_organization(&pool, organization, "Fixture", owner)
+            .await
+            .unwrap();
+
+        let mut transaction = pool.begin().await.unwrap();
+        let result = check_owner_change(
+            &mut transaction,
+            &Organization {
+                id: organization,
+                role: role.to_owned(),
+            },
+            "owner",
+        )
+        .await
+        .map_err(|e| e.to_string());
+        transaction.rollback().await.unwrap();
+
+        query_unchecked!("DELETE FROM accounts WHERE id = $1", owner)
+            .execute(&pool)
+            .await
+            .unwrap();
+        query_unchecked!("DELETE FROM organizations WHERE id = $1", organization)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        result
+    }
+
+    #[tokio::test]
+    async fn the_last_owner_stays() {
+        assert!(owner_change("owner").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn only_owners_change_owners() {
+        assert_eq!(
+            owner_change("admin").await,
+            Err(AuthError::Forbidden.to_string())
+        );
+    }
+}
@@ -0,0 +1,155 @@
+use crate::{
+    auth::AuthError,
+    environment::{Environment, Mail},
+    helpers::{cache, token},
+    session::Session,
+};
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+
+/// Always succeeds, whether or not the address belongs to an account, so it cannot be used to
+/// enumerate accounts. The lookup and the mail happen off the request so that both cases take as
+/// long, and an address is mailed at most once per `mail_interval`.
+pub async fn request_reset(env: &Environment, email: &str) -> anyhow::Result<()> {
+    let key = format!("mail:password_reset:{}", email.to_lowercase());
+    if !cache::set_nx_ex(&mut env.redis().await?, key, &(), env.mail_interval()).await? {
+        return Ok(());
+    }
+
+    let env = env.clone();
+    let email = email.to_owned();
+    tokio::spawn(async move {
+        if let Err(err) = send_reset(&env, &email).await {
+            tracing::error!("sending password reset failed: {:#}", err);
+        }
+    });
+
+    Ok(())
+}
+
+async fn send_reset(env: &Environment, email: &str) -> anyhow::Result<()> {
+    let account =
+        match crate::sql::account::get_account_id_password_by_email(env.database(), email).await? {
+            Some(account) => account,
+            None => return Ok(()),
+        };
+
+    let reset_token = token::generate();
+    let expiry = Utc::now() + Duration::seconds(env.password_reset_lifetime());
+
+    crate::sql::password_reset::create_password_reset(
+        env.database(),
+        &token::hash(&reset_token),
+        account.id,
+        expiry,
+    )
+    .await?;
+
+    env.mailer()
+        .send(Mail {
+            to: email.to_owned(),
+            subject: "Reset your password".to_owned(),
+            body: format!(
+                "Use the following token to reset your password, it expires at {}.\n\n{}",
+                expiry.to_rfc2822(),
+                reset_token
+            ),
+        })
+        .await
+}
+
+pub async fn reset(env: &Environment, reset_token: &str, password: &str) -> anyhow::Result<()> {
+    let account = crate::sql::password_reset::consume_password_reset(
+        env.database(),
+        &token::hash(reset_token),
+    )
+    .await?
+    .ok_or(AuthError::InvalidCredentials)?;
+
+    let password = env
+        .argon()
+        .hasher()
+        .with_password(password.to_owned())
+        .hash()
+        .or(Err(AuthError::ArgonError))?;
+
+    crate::sql::account::update_password(env.database(), account, &password).await?;
+    crate::session::revoke_account(env, account).await?;
+
+    Ok(())
+}
+
+pub async fn change(
+    env: &Environment,
+    session: &Session,
+    current_password: &str,
+    new_password: &str,
+    revoke_other_sessions: bool,
+) -> anyhow::Result<()> {
+    let account = session.account().await?;
+
+    let is_valid = env
+        .argon()
+        .verifier()
+        .with_hash(account.password.as_str())
+        .with_password(current_password.to_owned())
+        .verify()
+        .or(Err(AuthError::ArgonError))?;
+
+    if !is_valid {
+        return Err(AuthError::InvalidCredentials.into());
+    }
+
+    let password = env
+        .argon()
+        .hasher()
+        .with_password(new_password.to_owned())
+        .hash()
+        .or(Err(AuthError::ArgonError))?;
+
+    crate::sql::account::update_password(env.database(), account.id, &password).await?;
+
+    if revoke_other_sessions {
+        session.revoke_other_sessions().await?;
+    }
+
+    Ok(())
+}
+
+/// Replaces the password with one nobody knows, logs the account out everywhere and mails a reset
+/// token, so the owner has to pick a new password before logging in again.
+pub async fn force_reset(env: &Environment, account: Uuid) -> anyhow::Result<()> {
+    let email = crate::sql::account::get_account_by_id(env.database(), account)
+        .await?
+        .email;
+    let password = env
+        .argon()
+        .hasher()
+        .with_password(token::generate())
+        .hash()
+        .or(Err(AuthError::ArgonError))?;
+
+    crate::sql::account::update_password(env.database(), account, &password).await?;
+    crate::session::revoke_account(env, account).await?;
+
+    request_reset(env, &email).await
+}
+
+/// Re-hashes a password that was just verified against `old_hash` under the current parameters.
+pub async fn rehash(
+    env: &Environment,
+    account: Uuid,
+    old_hash: &str,
+    password: &str,
+) -> anyhow::Result<()> {
+    let password = env
+        .argon()
+        .hasher()
+        .with_password(password.to_owned())
+        .hash()
+        .or(Err(AuthError::ArgonError))?;
+
+    crate::sql::account::rehash_password(env.database(), account, old_hash, &password).await?;
+
+    Ok(())
+}
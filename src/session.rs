@@ -1,18 +1,84 @@
-use crate::{auth, helpers::cache, model, Environment};
+use crate::{
+    audit::{self, Event},
+    auth,
+    helpers::{cache, token},
+    model, Environment,
+};
 use chrono::Utc;
 use redis::aio::MultiplexedConnection;
 use serde::{de::DeserializeOwned, Serialize};
 use std::any::type_name;
 use std::convert::TryInto;
+use uuid::Uuid;
 
 #[derive(Clone)]
 pub struct Session {
     auth: auth::Session,
+    roles: Roles,
+    organization: Option<Organization>,
     env: Environment,
     redis: MultiplexedConnection,
 }
 
+/// Roles of the account and the permissions they grant, loaded fresh for every request so that
+/// changes apply at once.
+#[derive(Clone, Default, Debug)]
+struct Roles {
+    roles: Vec<String>,
+    permissions: Vec<String>,
+}
+
+impl Roles {
+    async fn load(env: &Environment, account: Uuid) -> anyhow::Result<Self> {
+        Ok(Self {
+            roles: crate::sql::role::get_account_roles(env.database(), account).await?,
+            permissions: crate::sql::role::get_account_permissions(env.database(), account).await?,
+        })
+    }
+}
+
+/// The organization the session acts in and the account's role there.
+#[derive(Clone, Debug)]
+pub struct Organization {
+    pub id: Uuid,
+    pub role: String,
+}
+
+impl Organization {
+    /// The choice is kept next to the session in Redis and checked against the memberships on
+    /// every request, so removed members lose access at once.
+    async fn load(
+        env: &Environment,
+        redis: &mut MultiplexedConnection,
+        auth: &auth::Session,
+    ) -> anyhow::Result<Option<Self>> {
+        let id: Uuid = match cache::get(redis, organization_key(&auth.key)).await {
+            Ok(id) => id,
+            Err(_) => return Ok(None),
+        };
+        let role = crate::sql::organization::get_membership_role(
+            &mut *env.database().acquire().await?,
+            id,
+            auth.account,
+        )
+        .await?;
+
+        Ok(role.map(|role| Self { id, role }))
+    }
+}
+
+fn organization_key(session_key: &str) -> String {
+    format!("session:{}:organization", session_key)
+}
+
+/// Names a session in the audit log. The key itself is a credential, a prefix of its digest is not.
+fn audit_detail(session_key: &str) -> String {
+    format!("session {}", &token::hash(session_key)[..12])
+}
+
 impl Session {
+    /// Sessions of disabled accounts are refused by `auth::session`, disabling an account revokes
+    /// its cached sessions as well.
     pub async fn new(env: Environment, jwt: &str, csrf: &str) -> anyhow::Result<Self> {
         let session_key = auth::claims(&env, &jwt, &csrf)?.session();
         let mut redis = env.redis().await?;
@@ -24,11 +90,214 @@ impl Session {
             Ok((auth, expiry))
         })
         .await?;
-        Ok(Self { env, auth, redis })
+        let roles = Roles::load(&env, auth.account).await?;
+        let organization = Organization::load(&env, &mut redis, &auth).await?;
+        Ok(Self {
+            env,
+            auth,
+            roles,
+            organization,
+            redis,
+        })
+    }
+
+    /// Accepts bearer tokens only for sessions issued to OAuth clients, logins still need CSRF.
+    pub async fn bearer(env: Environment, token: &str) -> anyhow::Result<Self> {
+        let csrf = env.jwt().decode(token)?.csrf();
+        let session = Self::new(env, token, &csrf).await?;
+        if session.client_id().is_none() {
+            return Err(auth::AuthError::InvalidCredentials.into());
+        }
+
+        Ok(session)
+    }
+
+    pub async fn from_api_key(env: Environment, key: &str) -> anyhow::Result<Self> {
+        let auth = auth::api_key_session(&env, key).await?;
+        let roles = Roles::load(&env, auth.account).await?;
+        let mut redis = env.redis().await?;
+        let organization = Organization::load(&env, &mut redis, &auth).await?;
+        Ok(Self {
+            env,
+            auth,
+            roles,
+            organization,
+            redis,
+        })
+    }
+
+    pub fn account_id(&self) -> Uuid {
+        self.auth.account
+    }
+
+    pub fn roles(&self) -> &[String] {
+        &self.roles.roles
+    }
+
+    pub fn has_permission(&self, permission: &str) -> bool {
+        self.roles
+            .permissions
+            .iter()
+            .any(|granted| granted == permission)
+    }
+
+    pub fn organization(&self) -> Option<&Organization> {
+        self.organization.as_ref()
+    }
+
+    /// Makes `organization` the active one for the rest of the session, membership is checked by
+    /// the caller.
+    pub async fn set_organization(&self, organization: Option<Uuid>) -> anyhow::Result<()> {
+        let mut redis = self.redis.clone();
+        let key = organization_key(&self.auth.key);
+        match organization {
+            Some(id) => {
+                let expiry = self.auth.expiry.signed_duration_since(Utc::now());
+                cache::set_ex(&mut redis, key, &id, expiry.num_seconds().try_into()?).await
+            }
+            None => cache::del(&mut redis, key).await,
+        }
+    }
+
+    pub fn impersonator(&self) -> Option<Uuid> {
+        self.auth.impersonator
+    }
+
+    /// Who the audit log holds responsible for the session's actions, the staff member during an
+    /// impersonation.
+    pub fn actor(&self) -> Uuid {
+        self.auth.impersonator.unwrap_or(self.auth.account)
+    }
+
+    /// Guards credential changes and grants, which must stay with the account's owner.
+    pub fn require_not_impersonating(&self) -> Result<(), auth::AuthError> {
+        match self.auth.impersonator {
+            Some(_) => Err(auth::AuthError::Impersonating),
+            None => Ok(()),
+        }
+    }
+
+    pub fn client_id(&self) -> Option<&str> {
+        self.auth.client_id.as_deref()
+    }
+
+    pub fn api_key_id(&self) -> Option<&str> {
+        self.auth.key.strip_prefix(auth::API_KEY_SESSION)
+    }
+
+    /// Sessions of OAuth clients and API keys are limited to their scopes, logins may do anything.
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.auth.scope.as_ref().map_or(true, |granted| {
+            granted.split(' ').any(|granted| granted == scope)
+        })
+    }
+
+    /// Guards what OAuth clients and API keys must never do, whatever scopes they were granted.
+    pub fn require_first_party(&self) -> Result<(), auth::AuthError> {
+        match self.auth.scope {
+            Some(_) => Err(auth::AuthError::InsufficientScope),
+            None => Ok(()),
+        }
+    }
+
+    pub async fn logout(&self) -> anyhow::Result<()> {
+        revoke(&self.env, &self.auth.key).await?;
+
+        let detail = audit_detail(&self.auth.key);
+        audit::record(
+            &self.env,
+            Event::SessionRevoked,
+            audit::Entry {
+                account: Some(self.auth.account),
+                actor: Some(self.actor()),
+                detail: Some(&detail),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        Ok(())
+    }
+
+    pub async fn sessions(&self) -> anyhow::Result<Vec<model::Session>> {
+        crate::sql::session::get_active_sessions(
+            &mut *self.env.database().acquire().await?,
+            self.auth.account,
+        )
+        .await
+    }
+
+    /// Revokes one of the account's sessions by the digest `sessions` exposes as its id.
+    pub async fn revoke_session(&self, id: &str) -> anyhow::Result<bool> {
+        let session = self
+            .sessions()
+            .await?
+            .into_iter()
+            .find(|session| session.digest() == id);
+        let session_key = match session {
+            Some(session) => session.key,
+            None => return Ok(false),
+        };
+
+        let revoked = crate::sql::session::invalidate_account_session(
+            self.env.database(),
+            self.auth.account,
+            &session_key,
+        )
+        .await?;
+
+        if revoked == 0 {
+            return Ok(false);
+        }
+        let detail = audit_detail(&session_key);
+        audit::record(
+            &self.env,
+            Event::SessionRevoked,
+            audit::Entry {
+                account: Some(self.auth.account),
+                actor: Some(self.actor()),
+                detail: Some(&detail),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        purge(&mut self.redis.clone(), &session_key).await?;
+        Ok(true)
+    }
+
+    pub async fn revoke_other_sessions(&self) -> anyhow::Result<usize> {
+        let keys = crate::sql::session::invalidate_other_sessions(
+            self.env.database(),
+            self.auth.account,
+            &self.auth.key,
+        )
+        .await?;
+
+        let mut redis = self.redis.clone();
+        for key in &keys {
+            purge(&mut redis, key).await?;
+        }
+        if !keys.is_empty() {
+            let detail = format!("{} other sessions", keys.len());
+            audit::record(
+                &self.env,
+                Event::SessionRevoked,
+                audit::Entry {
+                    account: Some(self.auth.account),
+                    actor: Some(self.actor()),
+                    detail: Some(&detail),
+                    ..Default::default()
+                },
+            )
+            .await;
+        }
+
+        Ok(keys.len())
     }
 
     pub async fn account(&self) -> anyhow::Result<model::Account> {
-        crate::sql::account::get_account_by_session_key(self.env.database(), &self.auth.key).await
+        crate::sql::account::get_account_by_id(self.env.database(), self.auth.account).await
     }
 
     pub async fn _set<T: Serialize>(&mut self, value: &T) -> anyhow::Result<()> {
@@ -53,3 +322,35 @@ impl Session {
         .await
     }
 }
+
+/// Evicts the cached `auth::Session` together with every value stored through `Session::_set`.
+pub async fn purge(redis: &mut MultiplexedConnection, session_key: &str) -> anyhow::Result<()> {
+    cache::del(redis, session_key).await?;
+    cache::del_matching(redis, &format!("session:{}:*", session_key)).await
+}
+
+pub async fn revoke(env: &Environment, session_key: &str) -> anyhow::Result<()> {
+    crate::sql::session::invalidate_session(env.database(), session_key).await?;
+    purge(&mut env.redis().await?, session_key).await
+}
+
+/// Revokes every session descending from the same login, used when a refresh token is replayed.
+pub async fn revoke_family(env: &Environment, family: &str) -> anyhow::Result<()> {
+    let keys = crate::sql::session::invalidate_family(env.database(), family).await?;
+    purge_all(env, &keys).await
+}
+
+pub async fn revoke_account(env: &Environment, account: Uuid) -> anyhow::Result<usize> {
+    let keys = crate::sql::session::invalidate_account_sessions(env.database(), account).await?;
+    purge_all(env, &keys).await?;
+    Ok(keys.len())
+}
+
+async fn purge_all(env: &Environment, session_keys: &[String]) -> anyhow::Result<()> {
+    let mut redis = env.redis().await?;
+    for key in session_keys {
+        purge(&mut redis, key).await?;
+    }
+
+    Ok(())
+}
@@ -1,10 +1,15 @@
 use crate::model;
-use sqlx::{postgres::PgPool, query_as, query_as_unchecked, query_unchecked};
+use sqlx::{
+    postgres::{PgConnection, PgPool},
+    query_as_unchecked, query_unchecked,
+};
 
-pub async fn get_all_accounts(connection: &PgPool) -> anyhow::Result<Vec<model::Account>> {
+pub async fn get_all_accounts(
+    connection: &mut PgConnection,
+) -> anyhow::Result<Vec<model::Account>> {
     query_as_unchecked!(
         model::Account,
-        "SELECT id, email, password, created_at, updated_at FROM accounts"
+        "SELECT id, email, password, email_verified_at, disabled_at, created_at, updated_at FROM accounts"
     )
     .fetch_all(connection)
     .await
@@ -15,7 +20,7 @@ pub async fn get_account(connection: &PgPool, email: &str) -> anyhow::Result<mod
     query_as_unchecked!(
         model::Account,
         r#"
-SELECT id, email, password, created_at, updated_at
+SELECT id, email, password, email_verified_at, disabled_at, created_at, updated_at
 FROM accounts 
 WHERE email = $1
 "#,
@@ -55,9 +60,10 @@ pub async fn update_email(
         model::Account,
         r#"
 UPDATE accounts
-  SET email = COALESCE($2, email)
+  SET email = COALESCE($2, email),
+    email_verified_at = CASE WHEN email = $2 THEN email_verified_at ELSE NULL END
   WHERE id = $1
-  RETURNING *
+  RETURNING id, email, password, email_verified_at, disabled_at, created_at, updated_at
 "#,
         id,
         email
@@ -67,39 +73,101 @@ UPDATE accounts
     .map_err(|e| e.into())
 }
 
-pub async fn get_account_by_session_key(
+pub async fn update_password(
     connection: &PgPool,
-    session_key: &str,
+    id: uuid::Uuid,
+    password: &str,
+) -> anyhow::Result<u64> {
+    query_unchecked!(
+        r#"
+UPDATE accounts
+  SET password = $2, updated_at = (NOW() AT TIME ZONE 'UTC')
+  WHERE id = $1
+"#,
+        id,
+        password
+    )
+    .execute(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+/// Swaps the hash only if it is still `old_password`, so a concurrent password change wins.
+pub async fn rehash_password(
+    connection: &PgPool,
+    id: uuid::Uuid,
+    old_password: &str,
+    password: &str,
+) -> anyhow::Result<u64> {
+    query_unchecked!(
+        r#"
+UPDATE accounts
+  SET password = $3
+  WHERE id = $1 AND password = $2
+"#,
+        id,
+        old_password,
+        password
+    )
+    .execute(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub async fn mark_email_verified(
+    connection: &PgPool,
+    id: uuid::Uuid,
+    email: &str,
+) -> anyhow::Result<u64> {
+    query_unchecked!(
+        r#"
+UPDATE accounts
+  SET email_verified_at = (NOW() AT TIME ZONE 'UTC')
+  WHERE id = $1 AND email = $2
+"#,
+        id,
+        email
+    )
+    .execute(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub async fn get_account_by_id(
+    connection: &PgPool,
+    id: uuid::Uuid,
 ) -> anyhow::Result<model::Account> {
-    Ok(query_as_unchecked!(
+    query_as_unchecked!(
         model::Account,
         r#"
-SELECT accounts.id, accounts.email, accounts.password, accounts.created_at, accounts.updated_at
-  FROM sessions
-  INNER JOIN accounts
-    ON sessions.account = accounts.id
-  WHERE
-    sessions.key = $1
+SELECT id, email, password, email_verified_at, disabled_at, created_at, updated_at
+  FROM accounts
+  WHERE id = $1
 "#,
-        session_key
+        id
     )
     .fetch_one(connection)
-    .await?)
+    .await
+    .map_err(|e| e.into())
 }
 
 pub struct AccountByEmail {
     pub id: uuid::Uuid,
     pub password: String,
+    pub email_verified_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub locked_until: Option<chrono::DateTime<chrono::Utc>>,
+    pub totp_enabled_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub disabled_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
 pub async fn get_account_id_password_by_email(
     connection: &PgPool,
     email: &str,
 ) -> anyhow::Result<Option<AccountByEmail>> {
-    query_as!(
+    query_as_unchecked!(
         AccountByEmail,
         r#"
-SELECT id, password
+SELECT id, password, email_verified_at, locked_until, totp_enabled_at, disabled_at
   FROM accounts
   WHERE email = $1
 "#,
@@ -110,24 +178,85 @@ SELECT id, password
     .map_err(|e| e.into())
 }
 
+/// Counts a failed login and locks the account for `duration` seconds once `threshold` consecutive
+/// failures are reached.
+pub async fn record_failed_login(
+    connection: &PgPool,
+    id: uuid::Uuid,
+    threshold: i32,
+    duration: i64,
+) -> anyhow::Result<u64> {
+    query_unchecked!(
+        r#"
+UPDATE accounts
+  SET failed_login_attempts = CASE
+      WHEN failed_login_attempts + 1 >= $2 THEN 0
+      ELSE failed_login_attempts + 1
+    END,
+    locked_until = CASE
+      WHEN failed_login_attempts + 1 >= $2 THEN (NOW() AT TIME ZONE 'UTC') + $3 * INTERVAL '1 second'
+      ELSE locked_until
+    END
+  WHERE id = $1
+"#,
+        id,
+        threshold,
+        duration as f64
+    )
+    .execute(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub async fn reset_failed_logins(connection: &PgPool, id: uuid::Uuid) -> anyhow::Result<u64> {
+    query_unchecked!(
+        r#"
+UPDATE accounts
+  SET failed_login_attempts = 0, locked_until = NULL
+  WHERE id = $1 AND (failed_login_attempts > 0 OR locked_until IS NOT NULL)
+"#,
+        id
+    )
+    .execute(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub struct NewSession<'a> {
+    pub key: &'a str,
+    pub csrf: &'a str,
+    pub family: &'a str,
+    pub refresh_token: &'a str,
+    pub expiry: chrono::DateTime<chrono::Utc>,
+    pub refresh_expiry: chrono::DateTime<chrono::Utc>,
+    pub client_id: Option<&'a str>,
+    pub scope: Option<&'a str>,
+    pub impersonator: Option<uuid::Uuid>,
+}
+
 pub async fn create_session(
     connection: &PgPool,
-    session_key: &str,
-    csrf: &str,
+    session: NewSession<'_>,
     id: uuid::Uuid,
     identity: crate::model::session::Identity,
-    expiry: chrono::DateTime<chrono::Utc>,
 ) -> anyhow::Result<u64> {
     query_unchecked!(
         r#"
-INSERT INTO sessions (key, csrf, account, identity, expiry)
-  VALUES ($1, $2, $3, $4, $5)
+INSERT INTO sessions (key, csrf, account, identity, expiry, family, refresh_token, refresh_expiry,
+    client_id, scope, impersonator)
+  VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
 "#,
-        session_key,
-        csrf,
+        session.key,
+        session.csrf,
         id,
         sqlx::types::Json(identity),
-        expiry
+        session.expiry,
+        session.family,
+        session.refresh_token,
+        session.refresh_expiry,
+        session.client_id,
+        session.scope,
+        session.impersonator
     )
     .execute(connection)
     .await
@@ -142,9 +271,12 @@ pub async fn get_csrf_validated_session(
     query_as_unchecked!(
         model::Session,
         r#"
-SELECT *
+SELECT sessions.*
   FROM sessions
-  WHERE key = $1 AND csrf = $2 AND expiry > NOW() AND NOT invalidated
+  INNER JOIN accounts
+    ON sessions.account = accounts.id
+  WHERE sessions.key = $1 AND sessions.csrf = $2 AND sessions.expiry > NOW()
+    AND NOT sessions.invalidated AND accounts.disabled_at IS NULL
 "#,
         session_key,
         csrf
@@ -153,3 +285,156 @@ SELECT *
     .await
     .map_err(|e| e.into())
 }
+
+/// Accounts whose email starts with `prefix`, wildcards in it are matched literally.
+pub async fn search_accounts(
+    connection: &mut PgConnection,
+    prefix: &str,
+    limit: i64,
+) -> anyhow::Result<Vec<model::Account>> {
+    let pattern = format!(
+        "{}%",
+        prefix
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_")
+    );
+
+    query_as_unchecked!(
+        model::Account,
+        r#"
+SELECT id, email, password, email_verified_at, disabled_at, created_at, updated_at
+  FROM accounts
+  WHERE email LIKE $1
+  ORDER BY email
+  LIMIT $2
+"#,
+        pattern,
+        limit
+    )
+    .fetch_all(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub async fn set_disabled(
+    connection: &PgPool,
+    id: uuid::Uuid,
+    disabled: bool,
+) -> anyhow::Result<model::Account> {
+    query_as_unchecked!(
+        model::Account,
+        r#"
+UPDATE accounts
+  SET disabled_at = CASE WHEN $2 THEN COALESCE(disabled_at, (NOW() AT TIME ZONE 'UTC')) END,
+    updated_at = (NOW() AT TIME ZONE 'UTC')
+  WHERE id = $1
+  RETURNING id, email, password, email_verified_at, disabled_at, created_at, updated_at
+"#,
+        id,
+        disabled
+    )
+    .fetch_one(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn disabling_keeps_the_first_timestamp() {
+        dotenv::dotenv().ok();
+        let pool = PgPool::new(&std::env::var("DATABASE_URL").unwrap())
+            .await
+            .unwrap();
+        let account = uuid::Uuid::new_v4();
+        query_unchecked!(
+            "INSERT INTO accounts (id, email, password) VALUES ($1, $2, '')",
+            account,
+            format!("{}@account.test", account)
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let disabled_at = set_disabled(&pool, account, true)
+            .await
+            .unwrap()
+            .disabled_at;
+        let again = set_disabled(&pool, account, true).await.unwrap();
+        let enabled = set_disabled(&pool, account, false).await.unwrap();
+
+        query_unchecked!("DELETE FROM accounts WHERE id = $1", account)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        assert!(disabled_at.is_some());
+        assert_eq!(again.disabled_at, disabled_at);
+        assert_eq!(enabled.disabled_at, None);
+    }
+
+    #[tokio::test]
+    async fn impersonators_stay_while_their_sessions_exist() {
+        dotenv::dotenv().ok();
+        let pool = PgPool::new(&std::env::var("DATABASE_URL").unwrap())
+            .await
+            .unwrap();
+        let (staff, account) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+        for id in &[staff, account] {
+            query_unchecked!(
+                "INSERT INTO accounts (id, email, password) VALUES ($1, $2, '')",
+                *id,
+                format!("{}@account.test", id)
+            )
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+        let key = uuid::Uuid::new_v4().to_string();
+        let expiry = chrono::Utc::now() + chrono::Duration::hours(1);
+        create_session(
+            &pool,
+            NewSession {
+                key: &key,
+                csrf: "",
+                family: &key,
+                refresh_token: &key,
+                expiry,
+                refresh_expiry: expiry,
+                client_id: None,
+                scope: None,
+                impersonator: Some(staff),
+            },
+            account,
+            Default::default(),
+        )
+        .await
+        .unwrap();
+
+        let impersonator = get_csrf_validated_session(&pool, &key, "")
+            .await
+            .unwrap()
+            .and_then(|session| session.impersonator);
+        let deleted = query_unchecked!("DELETE FROM accounts WHERE id = $1", staff)
+            .execute(&pool)
+            .await;
+
+        query_unchecked!("DELETE FROM sessions WHERE key = $1", key)
+            .execute(&pool)
+            .await
+            .unwrap();
+        query_unchecked!(
+            "DELETE FROM accounts WHERE id = ANY($1)",
+            &[staff, account][..]
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        assert_eq!(impersonator, Some(staff));
+        assert!(deleted.is_err());
+    }
+}
@@ -0,0 +1,120 @@
+use crate::model;
+use sqlx::{
+    postgres::{PgConnection, PgPool},
+    query_as_unchecked, query_unchecked,
+};
+
+pub struct NewApiKey<'a> {
+    pub id: uuid::Uuid,
+    pub name: &'a str,
+    pub prefix: &'a str,
+    pub secret: &'a str,
+    pub scope: &'a str,
+    pub expiry: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+pub async fn create_api_key(
+    connection: &PgPool,
+    account: uuid::Uuid,
+    api_key: NewApiKey<'_>,
+) -> anyhow::Result<model::ApiKey> {
+    query_as_unchecked!(
+        model::ApiKey,
+        r#"
+INSERT INTO api_keys (id, account, name, prefix, secret, scope, expiry)
+  VALUES ($1, $2, $3, $4, $5, $6, $7)
+  RETURNING id, name, prefix, scope, last_used_at, expiry, created_at
+"#,
+        api_key.id,
+        account,
+        api_key.name,
+        api_key.prefix,
+        api_key.secret,
+        api_key.scope,
+        api_key.expiry
+    )
+    .fetch_one(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub async fn get_api_keys(
+    connection: &mut PgConnection,
+    account: uuid::Uuid,
+) -> anyhow::Result<Vec<model::ApiKey>> {
+    query_as_unchecked!(
+        model::ApiKey,
+        r#"
+SELECT id, name, prefix, scope, last_used_at, expiry, created_at
+  FROM api_keys
+  WHERE account = $1
+  ORDER BY created_at DESC
+"#,
+        account
+    )
+    .fetch_all(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub async fn delete_api_key(
+    connection: &PgPool,
+    account: uuid::Uuid,
+    id: uuid::Uuid,
+) -> anyhow::Result<u64> {
+    query_unchecked!(
+        r#"
+DELETE FROM api_keys
+  WHERE id = $1 AND account = $2
+"#,
+        id,
+        account
+    )
+    .execute(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub struct ApiKeyByPrefix {
+    pub id: uuid::Uuid,
+    pub account: uuid::Uuid,
+    pub secret: String,
+    pub scope: String,
+    pub expiry: Option<chrono::DateTime<chrono::Utc>>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+pub async fn get_api_key_by_prefix(
+    connection: &PgPool,
+    prefix: &str,
+) -> anyhow::Result<Option<ApiKeyByPrefix>> {
+    query_as_unchecked!(
+        ApiKeyByPrefix,
+        r#"
+SELECT id, account, secret, scope, expiry, created_at
+  FROM api_keys
+  WHERE prefix = $1 AND (expiry IS NULL OR expiry > (NOW() AT TIME ZONE 'UTC'))
+    AND account IN (SELECT id FROM accounts WHERE disabled_at IS NULL)
+"#,
+        prefix
+    )
+    .fetch_optional(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+/// Bumps `last_used_at`, at most once a minute to spare a write on every request.
+pub async fn touch_api_key(connection: &PgPool, id: uuid::Uuid) -> anyhow::Result<u64> {
+    query_unchecked!(
+        r#"
+UPDATE api_keys
+  SET last_used_at = (NOW() AT TIME ZONE 'UTC')
+  WHERE id = $1
+    AND (last_used_at IS NULL OR last_used_at < (NOW() AT TIME ZONE 'UTC') - INTERVAL '1 minute')
+"#,
+        id
+    )
+    .execute(connection)
+    .await
+    .map_err(|e| e.into())
+}
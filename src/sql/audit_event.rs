@@ -0,0 +1,167 @@
+use crate::model;
+use sqlx::{postgres::PgPool, query_as_unchecked, query_unchecked};
+
+pub struct NewAuditEvent<'a> {
+    pub event: &'a str,
+    pub account: Option<uuid::Uuid>,
+    pub actor: Option<uuid::Uuid>,
+    pub ip: Option<String>,
+    pub detail: Option<&'a str>,
+}
+
+pub async fn create_audit_event(
+    connection: &PgPool,
+    event: NewAuditEvent<'_>,
+) -> anyhow::Result<u64> {
+    query_unchecked!(
+        r#"
+INSERT INTO audit_events (id, event, account, actor, ip, detail)
+  VALUES ($1, $2, $3, $4, $5, $6)
+"#,
+        uuid::Uuid::new_v4(),
+        event.event,
+        event.account,
+        event.actor,
+        event.ip,
+        event.detail
+    )
+    .execute(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub struct AuditEventFilter<'a> {
+    pub account: Option<uuid::Uuid>,
+    pub event: Option<&'a str>,
+    pub from: Option<chrono::DateTime<chrono::Utc>>,
+    pub until: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Newest events first, `from` is inclusive and `until` exclusive.
+pub async fn get_audit_events(
+    connection: &PgPool,
+    filter: AuditEventFilter<'_>,
+    limit: i64,
+    offset: i64,
+) -> anyhow::Result<Vec<model::AuditEvent>> {
+    query_as_unchecked!(
+        model::AuditEvent,
+        r#"
+SELECT id, event, account, actor, ip, detail, created_at
+  FROM audit_events
+  WHERE ($1::uuid IS NULL OR account = $1)
+    AND ($2::varchar IS NULL OR event = $2)
+    AND ($3::timestamp IS NULL OR created_at >= $3)
+    AND ($4::timestamp IS NULL OR created_at < $4)
+  ORDER BY created_at DESC, id
+  LIMIT $5 OFFSET $6
+"#,
+        filter.account,
+        filter.event,
+        filter.from,
+        filter.until,
+        limit,
+        offset
+    )
+    .fetch_all(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::tenant::{self, Tenant};
+
+    /// Commits events of a fresh account to `DATABASE_URL`. The log is append-only, so the rows
+    /// stay, the random account keeps them apart from other runs.
+    async fn fixture() -> (PgPool, uuid::Uuid) {
+        dotenv::dotenv().ok();
+        let pool = PgPool::new(&std::env::var("DATABASE_URL").unwrap())
+            .await
+            .unwrap();
+        let account = uuid::Uuid::new_v4();
+        for event in &["login.failed", "login.succeeded"] {
+            create_audit_event(
+                &pool,
+                NewAuditEvent {
+                    event,
+                    account: Some(account),
+                    actor: None,
+                    ip: None,
+                    detail: None,
+                },
+            )
+            .await
+            .unwrap();
+        }
+
+        (pool, account)
+    }
+
+    fn filter(account: uuid::Uuid, event: Option<&str>) -> AuditEventFilter<'_> {
+        AuditEventFilter {
+            account: Some(account),
+            event,
+            from: None,
+            until: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn events_are_filtered() {
+        let (pool, account) = fixture().await;
+
+        let all = get_audit_events(&pool, filter(account, None), 10, 0)
+            .await
+            .unwrap();
+        let failed = get_audit_events(&pool, filter(account, Some("login.failed")), 10, 0)
+            .await
+            .unwrap();
+
+        assert_eq!(all.len(), 2);
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].event, "login.failed");
+    }
+
+    #[tokio::test]
+    async fn events_cannot_be_changed() {
+        let (pool, account) = fixture().await;
+
+        let updated = query_unchecked!(
+            "UPDATE audit_events SET detail = 'changed' WHERE account = $1",
+            account
+        )
+        .execute(&pool)
+        .await;
+        let deleted = query_unchecked!("DELETE FROM audit_events WHERE account = $1", account)
+            .execute(&pool)
+            .await;
+
+        assert!(updated.is_err());
+        assert!(deleted.is_err());
+    }
+
+    #[tokio::test]
+    async fn tenants_cannot_read_the_log() {
+        let (pool, account) = fixture().await;
+        let mut transaction = tenant::begin(
+            &pool,
+            &Tenant {
+                account,
+                organization: None,
+                admin: false,
+            },
+        )
+        .await
+        .unwrap();
+
+        let read = sqlx::query("SELECT id FROM audit_events WHERE account = $1")
+            .bind(account)
+            .execute(&mut transaction)
+            .await;
+        assert!(read.is_err());
+
+        transaction.rollback().await.unwrap();
+    }
+}
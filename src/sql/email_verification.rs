@@ -0,0 +1,47 @@
+use sqlx::{postgres::PgPool, query_as_unchecked, query_unchecked};
+
+pub async fn create_email_verification(
+    connection: &PgPool,
+    token: &str,
+    account: uuid::Uuid,
+    email: &str,
+    expiry: chrono::DateTime<chrono::Utc>,
+) -> anyhow::Result<u64> {
+    query_unchecked!(
+        r#"
+INSERT INTO email_verifications (token, account, email, expiry)
+  VALUES ($1, $2, $3, $4)
+"#,
+        token,
+        account,
+        email,
+        expiry
+    )
+    .execute(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub struct EmailVerification {
+    pub account: uuid::Uuid,
+    pub email: String,
+}
+
+pub async fn consume_email_verification(
+    connection: &PgPool,
+    token: &str,
+) -> anyhow::Result<Option<EmailVerification>> {
+    query_as_unchecked!(
+        EmailVerification,
+        r#"
+UPDATE email_verifications
+  SET used_at = (NOW() AT TIME ZONE 'UTC')
+  WHERE token = $1 AND used_at IS NULL AND expiry > NOW()
+  RETURNING account, email
+"#,
+        token
+    )
+    .fetch_optional(connection)
+    .await
+    .map_err(|e| e.into())
+}
@@ -0,0 +1,47 @@
+use sqlx::{postgres::PgPool, query_as_unchecked, query_unchecked};
+
+pub async fn get_linked_account(
+    connection: &PgPool,
+    provider: &str,
+    subject: &str,
+) -> anyhow::Result<Option<uuid::Uuid>> {
+    struct LinkedAccount {
+        account: uuid::Uuid,
+    }
+
+    Ok(query_as_unchecked!(
+        LinkedAccount,
+        r#"
+SELECT account
+  FROM linked_identities
+  WHERE provider = $1 AND subject = $2
+"#,
+        provider,
+        subject
+    )
+    .fetch_optional(connection)
+    .await?
+    .map(|linked| linked.account))
+}
+
+pub async fn link_identity(
+    connection: &PgPool,
+    provider: &str,
+    subject: &str,
+    account: uuid::Uuid,
+    email: Option<&str>,
+) -> anyhow::Result<u64> {
+    query_unchecked!(
+        r#"
+INSERT INTO linked_identities (provider, subject, account, email)
+  VALUES ($1, $2, $3, $4)
+"#,
+        provider,
+        subject,
+        account,
+        email
+    )
+    .execute(connection)
+    .await
+    .map_err(|e| e.into())
+}
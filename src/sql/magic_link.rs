@@ -0,0 +1,158 @@
+use sqlx::{postgres::PgPool, query_as_unchecked, query_unchecked};
+
+pub async fn create_magic_link(
+    connection: &PgPool,
+    token: &str,
+    account: uuid::Uuid,
+    email: &str,
+    expiry: chrono::DateTime<chrono::Utc>,
+) -> anyhow::Result<u64> {
+    query_unchecked!(
+        r#"
+INSERT INTO magic_links (token, account, email, expiry)
+  VALUES ($1, $2, $3, $4)
+"#,
+        token,
+        account,
+        email,
+        expiry
+    )
+    .execute(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub struct MagicLink {
+    pub account: uuid::Uuid,
+    pub email: String,
+}
+
+/// Marks the link as used and yields its account, only once, only before it expires and only while
+/// the account still has the address the link was sent to.
+pub async fn consume_magic_link(
+    connection: &PgPool,
+    token: &str,
+) -> anyhow::Result<Option<MagicLink>> {
+    query_as_unchecked!(
+        MagicLink,
+        r#"
+UPDATE magic_links
+  SET used_at = (NOW() AT TIME ZONE 'UTC')
+  WHERE token = $1 AND used_at IS NULL AND expiry > NOW()
+    AND email = (SELECT email FROM accounts WHERE accounts.id = magic_links.account)
+  RETURNING account, email
+"#,
+        token
+    )
+    .fetch_optional(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, Utc};
+    use uuid::Uuid;
+
+    struct Fixture {
+        pool: PgPool,
+        account: Uuid,
+        email: String,
+    }
+
+    impl Fixture {
+        /// Commits an account to `DATABASE_URL`, which the query macros need to build anyway.
+        async fn new() -> Self {
+            dotenv::dotenv().ok();
+            let pool = PgPool::new(&std::env::var("DATABASE_URL").unwrap())
+                .await
+                .unwrap();
+            let account = Uuid::new_v4();
+            let email = format!("{}@magic-link.test", account);
+
+            query_unchecked!(
+                "INSERT INTO accounts (id, email, password) VALUES ($1, $2, '')",
+                account,
+                email
+            )
+            .execute(&pool)
+            .await
+            .unwrap();
+
+            Self {
+                pool,
+                account,
+                email,
+            }
+        }
+
+        async fn link(&self, expiry: Duration) -> String {
+            let token = Uuid::new_v4().to_string();
+            create_magic_link(
+                &self.pool,
+                &token,
+                self.account,
+                &self.email,
+                Utc::now() + expiry,
+            )
+            .await
+            .unwrap();
+
+            token
+        }
+
+        async fn consume(&self, token: &str) -> Option<Uuid> {
+            consume_magic_link(&self.pool, token)
+                .await
+                .unwrap()
+                .map(|link| link.account)
+        }
+
+        async fn remove(self) {
+            query_unchecked!("DELETE FROM accounts WHERE id = $1", self.account)
+                .execute(&self.pool)
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn links_are_used_once() {
+        let fixture = Fixture::new().await;
+        let token = fixture.link(Duration::minutes(15)).await;
+
+        assert_eq!(fixture.consume(&token).await, Some(fixture.account));
+        assert_eq!(fixture.consume(&token).await, None);
+
+        fixture.remove().await;
+    }
+
+    #[tokio::test]
+    async fn expired_links_are_refused() {
+        let fixture = Fixture::new().await;
+        let token = fixture.link(Duration::minutes(-1)).await;
+
+        assert_eq!(fixture.consume(&token).await, None);
+
+        fixture.remove().await;
+    }
+
+    #[tokio::test]
+    async fn links_to_a_previous_address_are_refused() {
+        let fixture = Fixture::new().await;
+        let token = fixture.link(Duration::minutes(15)).await;
+        query_unchecked!(
+            "UPDATE accounts SET email = $2 WHERE id = $1",
+            fixture.account,
+            format!("new-{}", fixture.email)
+        )
+        .execute(&fixture.pool)
+        .await
+        .unwrap();
+
+        assert_eq!(fixture.consume(&token).await, None);
+
+        fixture.remove().await;
+    }
+}
@@ -1 +1,15 @@
 pub mod account;
+pub mod api_key;
+pub mod audit_event;
+pub mod email_verification;
+pub mod linked_identity;
+pub mod magic_link;
+pub mod oauth_client;
+pub mod organization;
+pub mod password_reset;
+pub mod recovery_code;
+pub mod role;
+pub mod session;
+pub mod tenant;
+pub mod totp;
+pub mod webauthn;
@@ -0,0 +1,61 @@
+use sqlx::{postgres::PgPool, query_as_unchecked, query_unchecked};
+
+pub struct OauthClient {
+    pub id: String,
+    pub account: uuid::Uuid,
+    pub name: String,
+    pub secret: Option<String>,
+    pub redirect_uri: String,
+    pub scope: String,
+}
+
+pub async fn create_client(connection: &PgPool, client: &OauthClient) -> anyhow::Result<u64> {
+    query_unchecked!(
+        r#"
+INSERT INTO oauth_clients (id, account, name, secret, redirect_uri, scope)
+  VALUES ($1, $2, $3, $4, $5, $6)
+"#,
+        client.id,
+        client.account,
+        client.name,
+        client.secret,
+        client.redirect_uri,
+        client.scope
+    )
+    .execute(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub async fn get_client(connection: &PgPool, id: &str) -> anyhow::Result<Option<OauthClient>> {
+    query_as_unchecked!(
+        OauthClient,
+        r#"
+SELECT id, account, name, secret, redirect_uri, scope
+  FROM oauth_clients
+  WHERE id = $1
+"#,
+        id
+    )
+    .fetch_optional(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub async fn delete_client(
+    connection: &PgPool,
+    account: uuid::Uuid,
+    id: &str,
+) -> anyhow::Result<u64> {
+    query_unchecked!(
+        r#"
+DELETE FROM oauth_clients
+  WHERE id = $1 AND account = $2
+"#,
+        id,
+        account
+    )
+    .execute(connection)
+    .await
+    .map_err(|e| e.into())
+}
@@ -0,0 +1,515 @@
+use crate::model;
+use sqlx::{
+    postgres::{PgConnection, PgPool},
+    query_as_unchecked, query_unchecked,
+};
+
+pub async fn create_organization(
+    connection: &PgPool,
+    id: uuid::Uuid,
+    name: &str,
+    owner: uuid::Uuid,
+) -> anyhow::Result<u64> {
+    query_unchecked!(
+        r#"
+WITH organization AS (
+  INSERT INTO organizations (id, name)
+    VALUES ($1, $2)
+    RETURNING id
+)
+INSERT INTO memberships (organization, account, role)
+  SELECT id, $3, 'owner' FROM organization
+"#,
+        id,
+        name,
+        owner
+    )
+    .execute(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub async fn get_account_organizations(
+    connection: &mut PgConnection,
+    account: uuid::Uuid,
+) -> anyhow::Result<Vec<model::Organization>> {
+    query_as_unchecked!(
+        model::Organization,
+        r#"
+SELECT organizations.id, organizations.name, memberships.role, organizations.created_at, organizations.updated_at
+  FROM organizations
+  INNER JOIN memberships
+    ON organizations.id = memberships.organization
+  WHERE memberships.account = $1
+  ORDER BY organizations.name
+"#,
+        account
+    )
+    .fetch_all(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub async fn get_organization(
+    connection: &mut PgConnection,
+    id: uuid::Uuid,
+    account: uuid::Uuid,
+) -> anyhow::Result<Option<model::Organization>> {
+    query_as_unchecked!(
+        model::Organization,
+        r#"
+SELECT organizations.id, organizations.name, memberships.role, organizations.created_at, organizations.updated_at
+  FROM organizations
+  INNER JOIN memberships
+    ON organizations.id = memberships.organization
+  WHERE organizations.id = $1 AND memberships.account = $2
+"#,
+        id,
+        account
+    )
+    .fetch_optional(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+struct Role {
+    role: String,
+}
+
+pub async fn get_membership_role(
+    connection: &mut PgConnection,
+    organization: uuid::Uuid,
+    account: uuid::Uuid,
+) -> anyhow::Result<Option<String>> {
+    Ok(query_as_unchecked!(
+        Role,
+        r#"
+SELECT role
+  FROM memberships
+  WHERE organization = $1 AND account = $2
+"#,
+        organization,
+        account
+    )
+    .fetch_optional(connection)
+    .await?
+    .map(|row| row.role))
+}
+
+pub async fn get_members(
+    connection: &mut PgConnection,
+    organization: uuid::Uuid,
+) -> anyhow::Result<Vec<model::Member>> {
+    query_as_unchecked!(
+        model::Member,
+        r#"
+SELECT memberships.account, accounts.email, memberships.role, memberships.created_at
+  FROM memberships
+  INNER JOIN accounts
+    ON memberships.account = accounts.id
+  WHERE memberships.organization = $1
+  ORDER BY accounts.email
+"#,
+        organization
+    )
+    .fetch_all(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub async fn update_membership_role(
+    connection: &mut PgConnection,
+    organization: uuid::Uuid,
+    account: uuid::Uuid,
+    role: &str,
+) -> anyhow::Result<u64> {
+    query_unchecked!(
+        r#"
+UPDATE memberships
+  SET role = $3, updated_at = (NOW() AT TIME ZONE 'UTC')
+  WHERE organization = $1 AND account = $2
+"#,
+        organization,
+        account,
+        role
+    )
+    .execute(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub async fn delete_membership(
+    connection: &mut PgConnection,
+    organization: uuid::Uuid,
+    account: uuid::Uuid,
+) -> anyhow::Result<u64> {
+    query_unchecked!(
+        r#"
+DELETE FROM memberships
+  WHERE organization = $1 AND account = $2
+"#,
+        organization,
+        account
+    )
+    .execute(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+struct Count {
+    count: i64,
+}
+
+/// Counts the owners and locks their memberships until the transaction ends, so a concurrent
+/// change waits and counts again once this one is committed.
+pub async fn count_owners(
+    connection: &mut PgConnection,
+    organization: uuid::Uuid,
+) -> anyhow::Result<i64> {
+    Ok(query_as_unchecked!(
+        Count,
+        r#"
+SELECT COUNT(*) AS count
+  FROM (
+    SELECT account
+      FROM memberships
+      WHERE organization = $1 AND role = 'owner'
+      FOR UPDATE
+  ) AS owners
+"#,
+        organization
+    )
+    .fetch_one(connection)
+    .await?
+    .count)
+}
+
+pub async fn has_member_with_email(
+    connection: &PgPool,
+    organization: uuid::Uuid,
+    email: &str,
+) -> anyhow::Result<bool> {
+    Ok(query_as_unchecked!(
+        Count,
+        r#"
+SELECT COUNT(*) AS count
+  FROM memberships
+  INNER JOIN accounts
+    ON memberships.account = accounts.id
+  WHERE memberships.organization = $1 AND accounts.email = $2
+"#,
+        organization,
+        email
+    )
+    .fetch_one(connection)
+    .await?
+    .count
+        > 0)
+}
+
+pub struct NewInvitation<'a> {
+    pub id: uuid::Uuid,
+    pub token: &'a str,
+    pub organization: uuid::Uuid,
+    pub email: &'a str,
+    pub role: &'a str,
+    pub invited_by: uuid::Uuid,
+    pub expiry: chrono::DateTime<chrono::Utc>,
+}
+
+pub async fn create_invitation(
+    connection: &PgPool,
+    invitation: NewInvitation<'_>,
+) -> anyhow::Result<u64> {
+    query_unchecked!(
+        r#"
+INSERT INTO invitations (id, token, organization, email, role, invited_by, expiry)
+  VALUES ($1, $2, $3, $4, $5, $6, $7)
+"#,
+        invitation.id,
+        invitation.token,
+        invitation.organization,
+        invitation.email,
+        invitation.role,
+        invitation.invited_by,
+        invitation.expiry
+    )
+    .execute(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub async fn get_invitations(
+    connection: &mut PgConnection,
+    organization: uuid::Uuid,
+) -> anyhow::Result<Vec<model::Invitation>> {
+    query_as_unchecked!(
+        model::Invitation,
+        r#"
+SELECT id, email, role, expiry, created_at
+  FROM invitations
+  WHERE organization = $1 AND expiry > NOW()
+  ORDER BY created_at DESC
+"#,
+        organization
+    )
+    .fetch_all(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub struct AcceptedInvitation {
+    pub organization: uuid::Uuid,
+    pub role: String,
+}
+
+/// Deletes the invitation and yields it, only once, only before it expires and only to the
+/// account that verified the address it was sent to.
+pub async fn take_invitation(
+    connection: &mut PgConnection,
+    token: &str,
+    account: uuid::Uuid,
+) -> anyhow::Result<Option<AcceptedInvitation>> {
+    query_as_unchecked!(
+        AcceptedInvitation,
+        r#"
+DELETE FROM invitations
+  WHERE token = $1 AND expiry > NOW()
+    AND email = (
+      SELECT email FROM accounts WHERE accounts.id = $2 AND accounts.email_verified_at IS NOT NULL
+    )
+  RETURNING organization, role
+"#,
+        token,
+        account
+    )
+    .fetch_optional(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub async fn decline_invitation(connection: &PgPool, token: &str) -> anyhow::Result<u64> {
+    query_unchecked!(
+        r#"
+DELETE FROM invitations
+  WHERE token = $1
+"#,
+        token
+    )
+    .execute(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub async fn delete_invitation(
+    connection: &PgPool,
+    organization: uuid::Uuid,
+    id: uuid::Uuid,
+) -> anyhow::Result<u64> {
+    query_unchecked!(
+        r#"
+DELETE FROM invitations
+  WHERE id = $1 AND organization = $2
+"#,
+        id,
+        organization
+    )
+    .execute(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+/// Adds the account to the organization, keeping the role of an existing membership.
+pub async fn add_membership(
+    connection: &mut PgConnection,
+    organization: uuid::Uuid,
+    account: uuid::Uuid,
+    role: &str,
+) -> anyhow::Result<u64> {
+    query_unchecked!(
+        r#"
+INSERT INTO memberships (organization, account, role)
+  VALUES ($1, $2, $3)
+  ON CONFLICT DO NOTHING
+"#,
+        organization,
+        account,
+        role
+    )
+    .execute(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, Utc};
+    use uuid::Uuid;
+
+    struct Fixture {
+        pool: PgPool,
+        organization: Uuid,
+        owner: Uuid,
+        accounts: Vec<Uuid>,
+    }
+
+    impl Fixture {
+        /// Commits an organization and its owner to `DATABASE_URL`, which the query macros need
+        /// to build anyway.
+        async fn new() -> Self {
+            dotenv::dotenv().ok();
+            let pool = PgPool::new(&std::env::var("DATABASE_URL").unwrap())
+                .await
+                .unwrap();
+            let mut fixture = Self {
+                pool,
+                organization: Uuid::new_v4(),
+                owner: Uuid::nil(),
+                accounts: Vec::new(),
+            };
+            fixture.owner = fixture.account(true).await;
+            create_organization(
+                &fixture.pool,
+                fixture.organization,
+                "Fixture",
+                fixture.owner,
+            )
+            .await
+            .unwrap();
+
+            fixture
+        }
+
+        async fn account(&mut self, verified: bool) -> Uuid {
+            let account = Uuid::new_v4();
+            query_unchecked!(
+                r#"
+INSERT INTO accounts (id, email, password, email_verified_at)
+  VALUES ($1, $2, '', CASE WHEN $3 THEN NOW() END)
+"#,
+                account,
+                email(account),
+                verified
+            )
+            .execute(&self.pool)
+            .await
+            .unwrap();
+            self.accounts.push(account);
+
+            account
+        }
+
+        async fn invite(&self, email: &str, expiry: Duration) -> String {
+            let token = Uuid::new_v4().to_string();
+            create_invitation(
+                &self.pool,
+                NewInvitation {
+                    id: Uuid::new_v4(),
+                    token: &token,
+                    organization: self.organization,
+                    email,
+                    role: "member",
+                    invited_by: self.owner,
+                    expiry: Utc::now() + expiry,
+                },
+            )
+            .await
+            .unwrap();
+
+            token
+        }
+
+        async fn take(&self, token: &str, account: Uuid) -> Option<String> {
+            take_invitation(&mut self.pool.acquire().await.unwrap(), token, account)
+                .await
+                .unwrap()
+                .map(|invitation| invitation.role)
+        }
+
+        async fn remove(self) {
+            query_unchecked!("DELETE FROM organizations WHERE id = $1", self.organization)
+                .execute(&self.pool)
+                .await
+                .unwrap();
+            for account in &self.accounts {
+                query_unchecked!("DELETE FROM accounts WHERE id = $1", *account)
+                    .execute(&self.pool)
+                    .await
+                    .unwrap();
+            }
+        }
+    }
+
+    fn email(account: Uuid) -> String {
+        format!("{}@organization.test", account)
+    }
+
+    #[tokio::test]
+    async fn invitations_are_taken_once() {
+        let mut fixture = Fixture::new().await;
+        let account = fixture.account(true).await;
+        let token = fixture.invite(&email(account), Duration::days(1)).await;
+
+        assert_eq!(
+            fixture.take(&token, account).await.as_deref(),
+            Some("member")
+        );
+        assert_eq!(fixture.take(&token, account).await, None);
+
+        fixture.remove().await;
+    }
+
+    #[tokio::test]
+    async fn invitations_need_the_verified_address() {
+        let mut fixture = Fixture::new().await;
+        let unverified = fixture.account(false).await;
+        let other = fixture.account(true).await;
+        let token = fixture.invite(&email(unverified), Duration::days(1)).await;
+
+        assert_eq!(fixture.take(&token, unverified).await, None);
+        assert_eq!(fixture.take(&token, other).await, None);
+
+        fixture.remove().await;
+    }
+
+    #[tokio::test]
+    async fn expired_invitations_are_refused() {
+        let mut fixture = Fixture::new().await;
+        let account = fixture.account(true).await;
+        let token = fixture.invite(&email(account), Duration::minutes(-1)).await;
+
+        assert_eq!(fixture.take(&token, account).await, None);
+
+        fixture.remove().await;
+    }
+
+    #[tokio::test]
+    async fn owners_are_counted() {
+        let mut fixture = Fixture::new().await;
+        let account = fixture.account(true).await;
+        let mut connection = fixture.pool.acquire().await.unwrap();
+        add_membership(&mut connection, fixture.organization, account, "admin")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            count_owners(&mut connection, fixture.organization)
+                .await
+                .unwrap(),
+            1
+        );
+        update_membership_role(&mut connection, fixture.organization, account, "owner")
+            .await
+            .unwrap();
+        assert_eq!(
+            count_owners(&mut connection, fixture.organization)
+                .await
+                .unwrap(),
+            2
+        );
+
+        drop(connection);
+        fixture.remove().await;
+    }
+}
@@ -0,0 +1,56 @@
+use sqlx::{postgres::PgPool, query_as_unchecked, query_unchecked};
+
+struct Reset {
+    account: uuid::Uuid,
+}
+
+pub async fn create_password_reset(
+    connection: &PgPool,
+    token: &str,
+    account: uuid::Uuid,
+    expiry: chrono::DateTime<chrono::Utc>,
+) -> anyhow::Result<u64> {
+    query_unchecked!(
+        r#"
+INSERT INTO password_resets (token, account, expiry)
+  VALUES ($1, $2, $3)
+"#,
+        token,
+        account,
+        expiry
+    )
+    .execute(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+/// Marks the reset as used and yields its account, only once and only before it expires. Every
+/// other unused reset of the account is spent along with it, so none outlives the new password.
+pub async fn consume_password_reset(
+    connection: &PgPool,
+    token: &str,
+) -> anyhow::Result<Option<uuid::Uuid>> {
+    Ok(query_as_unchecked!(
+        Reset,
+        r#"
+WITH reset AS (
+  UPDATE password_resets
+    SET used_at = (NOW() AT TIME ZONE 'UTC')
+    WHERE token = $1 AND used_at IS NULL AND expiry > NOW()
+    RETURNING account
+), outstanding AS (
+  UPDATE password_resets
+    SET used_at = (NOW() AT TIME ZONE 'UTC')
+    FROM reset
+    WHERE password_resets.account = reset.account AND password_resets.token <> $1
+      AND password_resets.used_at IS NULL
+)
+SELECT account
+  FROM reset
+"#,
+        token
+    )
+    .fetch_optional(connection)
+    .await?
+    .map(|row| row.account))
+}
@@ -0,0 +1,60 @@
+use sqlx::{
+    postgres::{PgConnection, PgPool},
+    query_unchecked,
+};
+
+pub async fn create_recovery_code(
+    connection: &mut PgConnection,
+    id: uuid::Uuid,
+    account: uuid::Uuid,
+    code: &str,
+) -> anyhow::Result<u64> {
+    query_unchecked!(
+        r#"
+INSERT INTO recovery_codes (id, account, code)
+  VALUES ($1, $2, $3)
+"#,
+        id,
+        account,
+        code
+    )
+    .execute(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+/// Marks the account's unused code with the digest `code` as used, affecting no row otherwise.
+pub async fn use_recovery_code(
+    connection: &PgPool,
+    account: uuid::Uuid,
+    code: &str,
+) -> anyhow::Result<u64> {
+    query_unchecked!(
+        r#"
+UPDATE recovery_codes
+  SET used_at = (NOW() AT TIME ZONE 'UTC')
+  WHERE account = $1 AND code = $2 AND used_at IS NULL
+"#,
+        account,
+        code
+    )
+    .execute(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub async fn delete_recovery_codes(
+    connection: &mut PgConnection,
+    account: uuid::Uuid,
+) -> anyhow::Result<u64> {
+    query_unchecked!(
+        r#"
+DELETE FROM recovery_codes
+  WHERE account = $1
+"#,
+        account
+    )
+    .execute(connection)
+    .await
+    .map_err(|e| e.into())
+}
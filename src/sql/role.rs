@@ -0,0 +1,103 @@
+use sqlx::{postgres::PgPool, query_as_unchecked};
+
+struct Name {
+    name: String,
+}
+
+pub async fn get_account_roles(
+    connection: &PgPool,
+    account: uuid::Uuid,
+) -> anyhow::Result<Vec<String>> {
+    Ok(query_as_unchecked!(
+        Name,
+        r#"
+SELECT role AS name
+  FROM account_roles
+  WHERE account = $1
+"#,
+        account
+    )
+    .fetch_all(connection)
+    .await?
+    .into_iter()
+    .map(|row| row.name)
+    .collect())
+}
+
+pub async fn get_account_permissions(
+    connection: &PgPool,
+    account: uuid::Uuid,
+) -> anyhow::Result<Vec<String>> {
+    Ok(query_as_unchecked!(
+        Name,
+        r#"
+SELECT DISTINCT role_permissions.permission AS name
+  FROM account_roles
+  INNER JOIN role_permissions
+    ON account_roles.role = role_permissions.role
+  WHERE account_roles.account = $1
+"#,
+        account
+    )
+    .fetch_all(connection)
+    .await?
+    .into_iter()
+    .map(|row| row.name)
+    .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::query_unchecked;
+
+    /// Commits an account holding `roles` to `DATABASE_URL`, returning its permissions.
+    async fn permissions(roles: &[&str]) -> Vec<String> {
+        dotenv::dotenv().ok();
+        let pool = PgPool::new(&std::env::var("DATABASE_URL").unwrap())
+            .await
+            .unwrap();
+        let account = uuid::Uuid::new_v4();
+        query_unchecked!(
+            "INSERT INTO accounts (id, email, password) VALUES ($1, $2, '')",
+            account,
+            format!("{}@role.test", account)
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        for role in roles {
+            query_unchecked!(
+                "INSERT INTO account_roles (account, role) VALUES ($1, $2)",
+                account,
+                *role
+            )
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        let mut permissions = get_account_permissions(&pool, account).await.unwrap();
+        permissions.sort();
+
+        query_unchecked!("DELETE FROM accounts WHERE id = $1", account)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        permissions
+    }
+
+    #[tokio::test]
+    async fn admins_may_read_and_write_accounts() {
+        assert_eq!(
+            permissions(&["admin"]).await,
+            vec!["accounts:read", "accounts:write", "audit:read"]
+        );
+    }
+
+    #[tokio::test]
+    async fn accounts_without_roles_have_no_permissions() {
+        assert!(permissions(&[]).await.is_empty());
+    }
+}
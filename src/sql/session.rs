@@ -0,0 +1,210 @@
+use crate::model::{self, session::Identity};
+use sqlx::{
+    postgres::{PgConnection, PgPool},
+    query_as_unchecked, query_unchecked,
+    types::Json,
+};
+
+struct Key {
+    key: String,
+}
+
+pub async fn invalidate_session(connection: &PgPool, session_key: &str) -> anyhow::Result<u64> {
+    query_unchecked!(
+        r#"
+UPDATE sessions
+  SET invalidated = TRUE, updated_at = (NOW() AT TIME ZONE 'UTC')
+  WHERE key = $1 AND NOT invalidated
+"#,
+        session_key
+    )
+    .execute(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub async fn get_active_sessions(
+    connection: &mut PgConnection,
+    account: uuid::Uuid,
+) -> anyhow::Result<Vec<model::Session>> {
+    query_as_unchecked!(
+        model::Session,
+        r#"
+SELECT *
+  FROM sessions
+  WHERE account = $1 AND expiry > NOW() AND NOT invalidated
+  ORDER BY created_at DESC
+"#,
+        account
+    )
+    .fetch_all(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub async fn invalidate_account_session(
+    connection: &PgPool,
+    account: uuid::Uuid,
+    session_key: &str,
+) -> anyhow::Result<u64> {
+    query_unchecked!(
+        r#"
+UPDATE sessions
+  SET invalidated = TRUE, updated_at = (NOW() AT TIME ZONE 'UTC')
+  WHERE key = $1 AND account = $2 AND NOT invalidated
+"#,
+        session_key,
+        account
+    )
+    .execute(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub async fn invalidate_account_sessions(
+    connection: &PgPool,
+    account: uuid::Uuid,
+) -> anyhow::Result<Vec<String>> {
+    Ok(query_as_unchecked!(
+        Key,
+        r#"
+UPDATE sessions
+  SET invalidated = TRUE, updated_at = (NOW() AT TIME ZONE 'UTC')
+  WHERE account = $1 AND NOT invalidated
+  RETURNING key
+"#,
+        account
+    )
+    .fetch_all(connection)
+    .await?
+    .into_iter()
+    .map(|row| row.key)
+    .collect())
+}
+
+/// Invalidates every session of the account except `session_key`, returning the revoked keys.
+pub async fn invalidate_other_sessions(
+    connection: &PgPool,
+    account: uuid::Uuid,
+    session_key: &str,
+) -> anyhow::Result<Vec<String>> {
+    Ok(query_as_unchecked!(
+        Key,
+        r#"
+UPDATE sessions
+  SET invalidated = TRUE, updated_at = (NOW() AT TIME ZONE 'UTC')
+  WHERE account = $1 AND key <> $2 AND NOT invalidated
+  RETURNING key
+"#,
+        account,
+        session_key
+    )
+    .fetch_all(connection)
+    .await?
+    .into_iter()
+    .map(|row| row.key)
+    .collect())
+}
+
+pub async fn invalidate_client_sessions(
+    connection: &PgPool,
+    client_id: &str,
+) -> anyhow::Result<Vec<String>> {
+    Ok(query_as_unchecked!(
+        Key,
+        r#"
+UPDATE sessions
+  SET invalidated = TRUE, updated_at = (NOW() AT TIME ZONE 'UTC')
+  WHERE client_id = $1 AND NOT invalidated
+  RETURNING key
+"#,
+        client_id
+    )
+    .fetch_all(connection)
+    .await?
+    .into_iter()
+    .map(|row| row.key)
+    .collect())
+}
+
+pub async fn get_session(
+    connection: &PgPool,
+    session_key: &str,
+) -> anyhow::Result<Option<model::Session>> {
+    query_as_unchecked!(
+        model::Session,
+        r#"
+SELECT *
+  FROM sessions
+  WHERE key = $1 AND expiry > NOW() AND NOT invalidated
+"#,
+        session_key
+    )
+    .fetch_optional(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub struct RefreshedSession {
+    pub key: String,
+    pub account: uuid::Uuid,
+    pub family: Option<String>,
+    pub identity: Json<Identity>,
+}
+
+/// Marks the session owning `refresh_token` as rotated. Yields nothing when the token is unknown,
+/// expired or was already rotated, so concurrent refreshes cannot both succeed.
+pub async fn rotate_refresh_token(
+    connection: &PgPool,
+    refresh_token: &str,
+) -> anyhow::Result<Option<RefreshedSession>> {
+    query_as_unchecked!(
+        RefreshedSession,
+        r#"
+UPDATE sessions
+  SET invalidated = TRUE, rotated_at = (NOW() AT TIME ZONE 'UTC'), updated_at = (NOW() AT TIME ZONE 'UTC')
+  WHERE refresh_token = $1 AND refresh_expiry > NOW() AND rotated_at IS NULL AND NOT invalidated
+  RETURNING key, account, family, identity
+"#,
+        refresh_token
+    )
+    .fetch_optional(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub async fn get_rotated_session(
+    connection: &PgPool,
+    refresh_token: &str,
+) -> anyhow::Result<Option<RefreshedSession>> {
+    query_as_unchecked!(
+        RefreshedSession,
+        r#"
+SELECT key, account, family, identity
+  FROM sessions
+  WHERE refresh_token = $1 AND rotated_at IS NOT NULL
+"#,
+        refresh_token
+    )
+    .fetch_optional(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub async fn invalidate_family(connection: &PgPool, family: &str) -> anyhow::Result<Vec<String>> {
+    Ok(query_as_unchecked!(
+        Key,
+        r#"
+UPDATE sessions
+  SET invalidated = TRUE, updated_at = (NOW() AT TIME ZONE 'UTC')
+  WHERE family = $1 AND NOT invalidated
+  RETURNING key
+"#,
+        family
+    )
+    .fetch_all(connection)
+    .await?
+    .into_iter()
+    .map(|row| row.key)
+    .collect())
+}
@@ -0,0 +1,401 @@
+use sqlx::{
+    pool::PoolConnection,
+    postgres::{PgConnection, PgPool},
+    query, Transaction,
+};
+
+pub type TenantTransaction = Transaction<PoolConnection<PgConnection>>;
+
+/// Whose rows a transaction may see, enforced by the row-level security policies.
+pub struct Tenant {
+    pub account: uuid::Uuid,
+    pub organization: Option<uuid::Uuid>,
+    /// Keeps the transaction on the API's own role, which the policies don't apply to, for
+    /// accounts allowed to read every account
+    pub admin: bool,
+}
+
+/// Begins a transaction with the tenant applied, as `api_tenant` unless the tenant is an admin.
+/// `set_config` with `is_local` is `SET LOCAL` taking parameters, so the settings and the role end
+/// with the transaction and never reach the next user of the pooled connection.
+pub async fn begin(connection: &PgPool, tenant: &Tenant) -> anyhow::Result<TenantTransaction> {
+    let mut transaction = connection.begin().await?;
+    // Not a macro, those only execute statements that return no columns.
+    query(
+        r#"
+SELECT set_config('app.account_id', $1, true),
+  set_config('app.organization_id', $2, true),
+  set_config('role', $3, true)
+"#,
+    )
+    .bind(tenant.account.to_string())
+    .bind(
+        tenant
+            .organization
+            .map(|organization| organization.to_string())
+            .unwrap_or_default(),
+    )
+    .bind(if tenant.admin { "none" } else { "api_tenant" })
+    .execute(&mut transaction)
+    .await?;
+
+    Ok(transaction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::{query_as_unchecked, query_unchecked};
+    use uuid::Uuid;
+
+    struct Count {
+        count: i64,
+    }
+
+    /// Three accounts, the first two sharing an organization with a pending invitation.
+    struct Fixture {
+        pool: PgPool,
+        accounts: [Uuid; 3],
+        organization: Uuid,
+    }
+
+    impl Fixture {
+        /// Commits its rows to `DATABASE_URL`, which the query macros need to build anyway.
+        async fn new() -> Self {
+            dotenv::dotenv().ok();
+            let pool = PgPool::new(&std::env::var("DATABASE_URL").unwrap())
+                .await
+                .unwrap();
+            let accounts = [Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()];
+            let organization = Uuid::new_v4();
+
+            for account in accounts.iter() {
+                query_unchecked!(
+                    "INSERT INTO accounts (id, email, password) VALUES ($1, $2, '')",
+                    *account,
+                    format!("{}@tenant.test", account)
+                )
+                .execute(&pool)
+                .await
+                .unwrap();
+            }
+            query_unchecked!(
+                r#"
+WITH organization AS (
+  INSERT INTO organizations (id, name) VALUES ($1, 'tenant') RETURNING id
+), invitation AS (
+  INSERT INTO invitations (id, token, organization, email, role, expiry)
+    SELECT $1, $1::text, id, 'invited@tenant.test', 'member', NOW() + INTERVAL '1 day'
+      FROM organization
+)
+INSERT INTO memberships (organization, account, role)
+  SELECT id, $2::uuid, 'owner' FROM organization
+  UNION ALL
+  SELECT id, $3::uuid, 'member' FROM organization
+"#,
+                organization,
+                accounts[0],
+                accounts[1]
+            )
+            .execute(&pool)
+            .await
+            .unwrap();
+
+            Self {
+                pool,
+                accounts,
+                organization,
+            }
+        }
+
+        async fn begin(&self, account: usize, organization: bool) -> TenantTransaction {
+            begin(
+                &self.pool,
+                &Tenant {
+                    account: self.accounts[account],
+                    organization: if organization {
+                        Some(self.organization)
+                    } else {
+                        None
+                    },
+                    admin: false,
+                },
+            )
+            .await
+            .unwrap()
+        }
+
+        async fn remove(self) {
+            query_unchecked!(
+                "DELETE FROM sessions WHERE account = ANY($1)",
+                &self.accounts[..]
+            )
+            .execute(&self.pool)
+            .await
+            .unwrap();
+            query_unchecked!("DELETE FROM organizations WHERE id = $1", self.organization)
+                .execute(&self.pool)
+                .await
+                .unwrap();
+            for account in self.accounts.iter() {
+                query_unchecked!("DELETE FROM accounts WHERE id = $1", *account)
+                    .execute(&self.pool)
+                    .await
+                    .unwrap();
+            }
+        }
+    }
+
+    async fn sees_account(connection: &mut PgConnection, account: Uuid) -> bool {
+        query_as_unchecked!(
+            Count,
+            "SELECT COUNT(*) AS count FROM accounts WHERE id = $1",
+            account
+        )
+        .fetch_one(connection)
+        .await
+        .unwrap()
+        .count
+            > 0
+    }
+
+    async fn sees_organization(connection: &mut PgConnection, organization: Uuid) -> bool {
+        query_as_unchecked!(
+            Count,
+            "SELECT COUNT(*) AS count FROM organizations WHERE id = $1",
+            organization
+        )
+        .fetch_one(connection)
+        .await
+        .unwrap()
+        .count
+            > 0
+    }
+
+    async fn sees_invitations(connection: &mut PgConnection, organization: Uuid) -> bool {
+        query_as_unchecked!(
+            Count,
+            "SELECT COUNT(*) AS count FROM invitations WHERE organization = $1",
+            organization
+        )
+        .fetch_one(connection)
+        .await
+        .unwrap()
+        .count
+            > 0
+    }
+
+    #[tokio::test]
+    async fn accounts_see_only_themselves() {
+        let fixture = Fixture::new().await;
+        let mut transaction = fixture.begin(2, false).await;
+
+        assert!(sees_account(&mut transaction, fixture.accounts[2]).await);
+        assert!(!sees_account(&mut transaction, fixture.accounts[0]).await);
+        assert!(!sees_organization(&mut transaction, fixture.organization).await);
+
+        transaction.rollback().await.unwrap();
+        fixture.remove().await;
+    }
+
+    #[tokio::test]
+    async fn members_see_their_organization() {
+        let fixture = Fixture::new().await;
+        let mut transaction = fixture.begin(0, true).await;
+
+        assert!(sees_account(&mut transaction, fixture.accounts[1]).await);
+        assert!(!sees_account(&mut transaction, fixture.accounts[2]).await);
+        assert!(sees_organization(&mut transaction, fixture.organization).await);
+        assert!(sees_invitations(&mut transaction, fixture.organization).await);
+
+        transaction.rollback().await.unwrap();
+        fixture.remove().await;
+    }
+
+    #[tokio::test]
+    async fn members_see_only_the_active_organization() {
+        let fixture = Fixture::new().await;
+        let mut transaction = fixture.begin(0, false).await;
+
+        assert!(sees_organization(&mut transaction, fixture.organization).await);
+        assert!(!sees_account(&mut transaction, fixture.accounts[1]).await);
+        assert!(!sees_invitations(&mut transaction, fixture.organization).await);
+
+        transaction.rollback().await.unwrap();
+        fixture.remove().await;
+    }
+
+    #[tokio::test]
+    async fn hidden_accounts_cannot_be_changed() {
+        let fixture = Fixture::new().await;
+        let mut transaction = fixture.begin(2, false).await;
+
+        let updated = query_unchecked!(
+            "UPDATE accounts SET email = 'taken@tenant.test' WHERE id = $1",
+            fixture.accounts[0]
+        )
+        .execute(&mut transaction)
+        .await
+        .unwrap();
+        assert_eq!(updated, 0);
+
+        transaction.rollback().await.unwrap();
+        fixture.remove().await;
+    }
+
+    #[tokio::test]
+    async fn unset_tenants_see_nothing() {
+        let fixture = Fixture::new().await;
+        let mut transaction = fixture.pool.begin().await.unwrap();
+        query("SELECT set_config('role', 'api_tenant', true)")
+            .execute(&mut transaction)
+            .await
+            .unwrap();
+
+        assert!(!sees_account(&mut transaction, fixture.accounts[0]).await);
+        assert!(!sees_organization(&mut transaction, fixture.organization).await);
+
+        transaction.rollback().await.unwrap();
+        fixture.remove().await;
+    }
+
+    #[tokio::test]
+    async fn admins_see_every_account() {
+        let fixture = Fixture::new().await;
+        let mut transaction = begin(
+            &fixture.pool,
+            &Tenant {
+                account: fixture.accounts[2],
+                organization: None,
+                admin: true,
+            },
+        )
+        .await
+        .unwrap();
+
+        assert!(sees_account(&mut transaction, fixture.accounts[0]).await);
+
+        transaction.rollback().await.unwrap();
+        fixture.remove().await;
+    }
+
+    #[tokio::test]
+    async fn members_cannot_change_other_members() {
+        let fixture = Fixture::new().await;
+        let mut transaction = fixture.begin(1, true).await;
+
+        let updated = query_unchecked!(
+            "UPDATE accounts SET email = 'taken@tenant.test' WHERE id = $1",
+            fixture.accounts[0]
+        )
+        .execute(&mut transaction)
+        .await
+        .unwrap();
+        let deleted = query_unchecked!(
+            "DELETE FROM memberships WHERE account = $1",
+            fixture.accounts[0]
+        )
+        .execute(&mut transaction)
+        .await
+        .unwrap();
+        assert_eq!((updated, deleted), (0, 0));
+
+        transaction.rollback().await.unwrap();
+        fixture.remove().await;
+    }
+
+    #[tokio::test]
+    async fn members_may_leave() {
+        let fixture = Fixture::new().await;
+        let mut transaction = fixture.begin(1, true).await;
+
+        let deleted = query_unchecked!(
+            "DELETE FROM memberships WHERE account = $1",
+            fixture.accounts[1]
+        )
+        .execute(&mut transaction)
+        .await
+        .unwrap();
+        assert_eq!(deleted, 1);
+
+        transaction.rollback().await.unwrap();
+        fixture.remove().await;
+    }
+
+    #[tokio::test]
+    async fn accounts_cannot_join_by_themselves() {
+        let fixture = Fixture::new().await;
+        let mut transaction = fixture.begin(2, false).await;
+
+        let inserted = query_unchecked!(
+            "INSERT INTO memberships (organization, account, role) VALUES ($1, $2, 'owner')",
+            fixture.organization,
+            fixture.accounts[2]
+        )
+        .execute(&mut transaction)
+        .await;
+        assert!(inserted.is_err());
+
+        transaction.rollback().await.unwrap();
+        fixture.remove().await;
+    }
+
+    #[tokio::test]
+    async fn own_rows_cannot_be_handed_to_other_accounts() {
+        let fixture = Fixture::new().await;
+        let mut transaction = fixture.begin(1, true).await;
+
+        let inserted = query_unchecked!(
+            r#"
+INSERT INTO api_keys (id, account, name, prefix, secret, scope)
+  VALUES ($1, $2, 'key', $3, '', '')
+"#,
+            Uuid::new_v4(),
+            fixture.accounts[0],
+            Uuid::new_v4().to_string()[..16].to_owned()
+        )
+        .execute(&mut transaction)
+        .await;
+        assert!(inserted.is_err());
+
+        transaction.rollback().await.unwrap();
+        fixture.remove().await;
+    }
+
+    #[tokio::test]
+    async fn sessions_of_other_members_are_hidden() {
+        let fixture = Fixture::new().await;
+        for account in fixture.accounts[..2].iter() {
+            query_unchecked!(
+                "INSERT INTO sessions (key, csrf, account, identity) VALUES ($1, '', $2, '{}')",
+                account.to_string(),
+                *account
+            )
+            .execute(&fixture.pool)
+            .await
+            .unwrap();
+        }
+        let mut transaction = fixture.begin(1, true).await;
+
+        let sessions = query_as_unchecked!(
+            Count,
+            "SELECT COUNT(*) AS count FROM sessions WHERE account = ANY($1)",
+            &fixture.accounts[..2]
+        )
+        .fetch_one(&mut transaction)
+        .await
+        .unwrap()
+        .count;
+        let updated = query_unchecked!(
+            "UPDATE sessions SET invalidated = TRUE WHERE account = $1",
+            fixture.accounts[0]
+        )
+        .execute(&mut transaction)
+        .await
+        .unwrap();
+        assert_eq!((sessions, updated), (1, 0));
+
+        transaction.rollback().await.unwrap();
+        fixture.remove().await;
+    }
+}
@@ -0,0 +1,102 @@
+use sqlx::{
+    postgres::{PgConnection, PgPool},
+    query_as_unchecked, query_unchecked,
+};
+
+pub struct TotpState {
+    pub totp_secret: Option<String>,
+    pub totp_enabled_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub totp_last_step: Option<i64>,
+}
+
+pub async fn get_totp(connection: &PgPool, account: uuid::Uuid) -> anyhow::Result<TotpState> {
+    query_as_unchecked!(
+        TotpState,
+        r#"
+SELECT totp_secret, totp_enabled_at, totp_last_step
+  FROM accounts
+  WHERE id = $1
+"#,
+        account
+    )
+    .fetch_one(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+/// Stores a secret awaiting confirmation, replacing any earlier unconfirmed one.
+pub async fn set_pending_totp(
+    connection: &PgPool,
+    account: uuid::Uuid,
+    secret: &str,
+) -> anyhow::Result<u64> {
+    query_unchecked!(
+        r#"
+UPDATE accounts
+  SET totp_secret = $2, totp_last_step = NULL
+  WHERE id = $1 AND totp_enabled_at IS NULL
+"#,
+        account,
+        secret
+    )
+    .execute(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub async fn enable_totp(
+    connection: &PgPool,
+    account: uuid::Uuid,
+    step: i64,
+) -> anyhow::Result<u64> {
+    query_unchecked!(
+        r#"
+UPDATE accounts
+  SET totp_enabled_at = (NOW() AT TIME ZONE 'UTC'), totp_last_step = $2
+  WHERE id = $1 AND totp_secret IS NOT NULL AND totp_enabled_at IS NULL
+"#,
+        account,
+        step
+    )
+    .execute(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub async fn disable_totp(
+    connection: &mut PgConnection,
+    account: uuid::Uuid,
+) -> anyhow::Result<u64> {
+    query_unchecked!(
+        r#"
+UPDATE accounts
+  SET totp_secret = NULL, totp_enabled_at = NULL, totp_last_step = NULL
+  WHERE id = $1
+"#,
+        account
+    )
+    .execute(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+/// Records the time step of an accepted code, failing to match if a concurrent request already
+/// used this or a later step.
+pub async fn record_totp_step(
+    connection: &PgPool,
+    account: uuid::Uuid,
+    step: i64,
+) -> anyhow::Result<u64> {
+    query_unchecked!(
+        r#"
+UPDATE accounts
+  SET totp_last_step = $2
+  WHERE id = $1 AND (totp_last_step IS NULL OR totp_last_step < $2)
+"#,
+        account,
+        step
+    )
+    .execute(connection)
+    .await
+    .map_err(|e| e.into())
+}
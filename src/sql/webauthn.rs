@@ -0,0 +1,105 @@
+use sqlx::{postgres::PgPool, query_as_unchecked, query_unchecked};
+
+pub async fn create_credential(
+    connection: &PgPool,
+    id: uuid::Uuid,
+    account: uuid::Uuid,
+    credential_id: &str,
+    public_key: &[u8],
+    sign_count: i64,
+    name: Option<&str>,
+) -> anyhow::Result<u64> {
+    query_unchecked!(
+        r#"
+INSERT INTO webauthn_credentials (id, account, credential_id, public_key, sign_count, name)
+  VALUES ($1, $2, $3, $4, $5, $6)
+"#,
+        id,
+        account,
+        credential_id,
+        public_key,
+        sign_count,
+        name
+    )
+    .execute(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub async fn get_credential_ids(
+    connection: &PgPool,
+    account: uuid::Uuid,
+) -> anyhow::Result<Vec<String>> {
+    struct CredentialId {
+        credential_id: String,
+    }
+
+    Ok(query_as_unchecked!(
+        CredentialId,
+        r#"
+SELECT credential_id
+  FROM webauthn_credentials
+  WHERE account = $1
+"#,
+        account
+    )
+    .fetch_all(connection)
+    .await?
+    .into_iter()
+    .map(|row| row.credential_id)
+    .collect())
+}
+
+pub struct Credential {
+    pub id: uuid::Uuid,
+    pub account: uuid::Uuid,
+    pub public_key: Vec<u8>,
+    pub sign_count: i64,
+    pub email_verified_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub locked_until: Option<chrono::DateTime<chrono::Utc>>,
+    pub disabled_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+pub async fn get_credential(
+    connection: &PgPool,
+    credential_id: &str,
+) -> anyhow::Result<Option<Credential>> {
+    query_as_unchecked!(
+        Credential,
+        r#"
+SELECT webauthn_credentials.id, webauthn_credentials.account, webauthn_credentials.public_key,
+    webauthn_credentials.sign_count, accounts.email_verified_at, accounts.locked_until,
+    accounts.disabled_at
+  FROM webauthn_credentials
+  INNER JOIN accounts
+    ON webauthn_credentials.account = accounts.id
+  WHERE webauthn_credentials.credential_id = $1
+"#,
+        credential_id
+    )
+    .fetch_optional(connection)
+    .await
+    .map_err(|e| e.into())
+}
+
+/// Stores the authenticator's new signature counter, unless another login raced past it.
+pub async fn record_credential_use(
+    connection: &PgPool,
+    id: uuid::Uuid,
+    previous_sign_count: i64,
+    sign_count: i64,
+) -> anyhow::Result<u64> {
+    query_unchecked!(
+        r#"
+UPDATE webauthn_credentials
+  SET sign_count = $3, last_used_at = (NOW() AT TIME ZONE 'UTC')
+  WHERE id = $1 AND sign_count = $2
+"#,
+        id,
+        previous_sign_count,
+        sign_count
+    )
+    .execute(connection)
+    .await
+    .map_err(|e| e.into())
+}
@@ -0,0 +1,254 @@
+use crate::{
+    auth::AuthError,
+    environment::Environment,
+    helpers::{cache, rate_limit, token},
+    model::session::Identity,
+    session::Session,
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Seconds a pending login may take to complete the second factor.
+const CHALLENGE_LIFETIME: usize = 300;
+const RECOVERY_CODES: usize = 10;
+/// Unambiguous characters only, recovery codes are typed in by hand.
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+pub struct Enrollment {
+    pub uri: String,
+    pub secret: String,
+}
+
+/// A login that passed the password check and waits for its second factor.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PendingLogin {
+    pub account: Uuid,
+    pub lifetime: Option<i64>,
+    pub identity: Identity,
+}
+
+pub async fn enroll(env: &Environment, session: &Session) -> anyhow::Result<Enrollment> {
+    let account = session.account().await?;
+    let state = crate::sql::totp::get_totp(env.database(), account.id).await?;
+    if state.totp_enabled_at.is_some() {
+        return Err(anyhow::anyhow!(
+            "two-factor authentication is already enabled"
+        ));
+    }
+
+    let totp = env.totp();
+    let secret = totp.generate_secret();
+    crate::sql::totp::set_pending_totp(env.database(), account.id, &totp.encrypt(&secret)?).await?;
+
+    Ok(Enrollment {
+        uri: totp.uri(&account.email, &secret),
+        secret: totp.encode_secret(&secret),
+    })
+}
+
+/// Enables TOTP and hands out the initial recovery codes.
+pub async fn confirm(
+    env: &Environment,
+    session: &Session,
+    code: &str,
+) -> anyhow::Result<Vec<String>> {
+    let account = session.account_id();
+    let state = crate::sql::totp::get_totp(env.database(), account).await?;
+    let secret = match (state.totp_secret, state.totp_enabled_at) {
+        (Some(secret), None) => env.totp().decrypt(&secret)?,
+        _ => return Err(anyhow::anyhow!("no two-factor enrollment is pending")),
+    };
+
+    let step = env
+        .totp()
+        .verify(&secret, code, None)
+        .ok_or(AuthError::InvalidCredentials)?;
+
+    crate::sql::totp::enable_totp(env.database(), account, step).await?;
+
+    generate_recovery_codes(env, account).await
+}
+
+pub async fn disable(env: &Environment, session: &Session, code: &str) -> anyhow::Result<()> {
+    let account = session.account_id();
+    verify(env, account, code).await?;
+
+    let mut transaction = env.database().begin().await?;
+    crate::sql::totp::disable_totp(&mut transaction, account).await?;
+    crate::sql::recovery_code::delete_recovery_codes(&mut transaction, account).await?;
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Second login step, accepting either a TOTP code or one of the recovery codes.
+pub async fn verify_any(env: &Environment, account: Uuid, code: &str) -> anyhow::Result<()> {
+    let code = code.trim();
+    if code.chars().all(|c| c.is_ascii_digit()) {
+        verify(env, account, code).await
+    } else {
+        redeem_recovery_code(env, account, code).await
+    }
+}
+
+/// Accepts a code of the account's enabled TOTP secret, at most once.
+pub async fn verify(env: &Environment, account: Uuid, code: &str) -> anyhow::Result<()> {
+    let state = crate::sql::totp::get_totp(env.database(), account).await?;
+    let secret = match (state.totp_secret, state.totp_enabled_at) {
+        (Some(secret), Some(_)) => env.totp().decrypt(&secret)?,
+        _ => return Err(AuthError::InvalidCredentials.into()),
+    };
+
+    let step = env
+        .totp()
+        .verify(&secret, code, state.totp_last_step)
+        .ok_or(AuthError::InvalidCredentials)?;
+
+    if crate::sql::totp::record_totp_step(env.database(), account, step).await? == 0 {
+        return Err(AuthError::InvalidCredentials.into());
+    }
+
+    Ok(())
+}
+
+/// Replaces every recovery code of the account with a fresh set, returned in plain text once. A
+/// current TOTP code is required, a stolen session alone must not mint new codes.
+pub async fn regenerate_recovery_codes(
+    env: &Environment,
+    session: &Session,
+    code: &str,
+) -> anyhow::Result<Vec<String>> {
+    let account = session.account_id();
+    let state = crate::sql::totp::get_totp(env.database(), account).await?;
+    if state.totp_enabled_at.is_none() {
+        return Err(anyhow::anyhow!("two-factor authentication is not enabled"));
+    }
+    verify(env, account, code).await?;
+
+    generate_recovery_codes(env, account).await
+}
+
+/// Swaps a fresh set of codes for the old ones in a single transaction, so a failure never leaves
+/// the account without recovery codes.
+async fn generate_recovery_codes(env: &Environment, account: Uuid) -> anyhow::Result<Vec<String>> {
+    let codes: Vec<String> = (0..RECOVERY_CODES)
+        .map(|_| generate_recovery_code())
+        .collect();
+
+    let mut transaction = env.database().begin().await?;
+    crate::sql::recovery_code::delete_recovery_codes(&mut transaction, account).await?;
+    for code in &codes {
+        crate::sql::recovery_code::create_recovery_code(
+            &mut transaction,
+            Uuid::new_v4(),
+            account,
+            &hash_recovery_code(account, code),
+        )
+        .await?;
+    }
+    transaction.commit().await?;
+
+    Ok(codes)
+}
+
+async fn redeem_recovery_code(env: &Environment, account: Uuid, code: &str) -> anyhow::Result<()> {
+    let code = hash_recovery_code(account, code);
+    if crate::sql::recovery_code::use_recovery_code(env.database(), account, &code).await? == 0 {
+        return Err(AuthError::InvalidCredentials.into());
+    }
+
+    Ok(())
+}
+
+fn generate_recovery_code() -> String {
+    let mut rng = rand::thread_rng();
+    let code: String = (0..10)
+        .map(|_| RECOVERY_CODE_ALPHABET[rng.gen_range(0, RECOVERY_CODE_ALPHABET.len())] as char)
+        .collect();
+
+    format!("{}-{}", &code[..5], &code[5..])
+}
+
+/// Codes are stored as a fast digest so they can be looked up instead of verified one by one
+/// against Argon hashes. The account is mixed in so that equal codes of two accounts differ.
+fn hash_recovery_code(account: Uuid, code: &str) -> String {
+    let code: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase();
+
+    token::hash(&format!("{}:{}", account, code))
+}
+
+pub async fn challenge(env: &Environment, pending: &PendingLogin) -> anyhow::Result<String> {
+    let challenge = token::generate();
+    cache::set_ex(
+        &mut env.redis().await?,
+        format!("2fa:{}", token::hash(&challenge)),
+        pending,
+        CHALLENGE_LIFETIME,
+    )
+    .await?;
+
+    Ok(challenge)
+}
+
+/// Looks up a pending login, allowing only a handful of attempts per account to guess the code.
+pub async fn pending(env: &Environment, challenge: &str) -> anyhow::Result<PendingLogin> {
+    let mut redis = env.redis().await?;
+    let pending: PendingLogin = cache::get(&mut redis, format!("2fa:{}", token::hash(challenge)))
+        .await
+        .or(Err(AuthError::InvalidCredentials))?;
+
+    let key = format!("rate_limit:2fa:{}", pending.account);
+    rate_limit::sliding_window(&mut redis, &key, 5, CHALLENGE_LIFETIME as u64).await?;
+
+    Ok(pending)
+}
+
+pub async fn complete(env: &Environment, challenge: &str) -> anyhow::Result<()> {
+    cache::del(
+        &mut env.redis().await?,
+        format!("2fa:{}", token::hash(challenge)),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recovery_codes_are_grouped_from_the_alphabet() {
+        let code = generate_recovery_code();
+        assert_eq!(code.len(), 11);
+        assert_eq!(&code[5..6], "-");
+        assert!(code
+            .bytes()
+            .filter(|c| *c != b'-')
+            .all(|c| RECOVERY_CODE_ALPHABET.contains(&c)));
+    }
+
+    #[test]
+    fn recovery_codes_hash_as_typed() {
+        let account = Uuid::new_v4();
+        let code = generate_recovery_code();
+        let typed = format!(" {} ", code.replace("-", "").to_uppercase());
+        assert_eq!(
+            hash_recovery_code(account, &code),
+            hash_recovery_code(account, &typed)
+        );
+    }
+
+    #[test]
+    fn recovery_codes_hash_per_account() {
+        let code = generate_recovery_code();
+        assert_ne!(
+            hash_recovery_code(Uuid::new_v4(), &code),
+            hash_recovery_code(Uuid::new_v4(), &code)
+        );
+        assert!(!hash_recovery_code(Uuid::new_v4(), &code).contains(&code[..5]));
+    }
+}
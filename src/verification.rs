@@ -0,0 +1,81 @@
+use crate::{
+    auth::AuthError,
+    environment::{Environment, Mail},
+    helpers::{cache, token},
+};
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+
+pub async fn send(env: &Environment, account: Uuid, email: &str) -> anyhow::Result<()> {
+    let verification_token = token::generate();
+    let expiry = Utc::now() + Duration::seconds(env.email_verification_lifetime());
+
+    crate::sql::email_verification::create_email_verification(
+        env.database(),
+        &token::hash(&verification_token),
+        account,
+        email,
+        expiry,
+    )
+    .await?;
+
+    env.mailer()
+        .send(Mail {
+            to: email.to_owned(),
+            subject: "Verify your email address".to_owned(),
+            body: format!(
+                "Use the following token to verify your email address, it expires at {}.\n\n{}",
+                expiry.to_rfc2822(),
+                verification_token
+            ),
+        })
+        .await
+}
+
+/// Like `password::request_reset` this never reveals whether the address belongs to an account.
+pub async fn resend(env: &Environment, email: &str) -> anyhow::Result<()> {
+    let key = format!("mail:verification:{}", email.to_lowercase());
+    if !cache::set_nx_ex(&mut env.redis().await?, key, &(), env.mail_interval()).await? {
+        return Ok(());
+    }
+
+    let env = env.clone();
+    let email = email.to_owned();
+    tokio::spawn(async move {
+        if let Err(err) = resend_unverified(&env, &email).await {
+            tracing::error!("resending verification failed: {:#}", err);
+        }
+    });
+
+    Ok(())
+}
+
+async fn resend_unverified(env: &Environment, email: &str) -> anyhow::Result<()> {
+    match crate::sql::account::get_account_id_password_by_email(env.database(), email).await? {
+        Some(account) if account.email_verified_at.is_none() => send(env, account.id, email).await,
+        _ => Ok(()),
+    }
+}
+
+pub async fn verify(env: &Environment, verification_token: &str) -> anyhow::Result<()> {
+    let verification = crate::sql::email_verification::consume_email_verification(
+        env.database(),
+        &token::hash(verification_token),
+    )
+    .await?
+    .ok_or(AuthError::InvalidCredentials)?;
+
+    // The address may have changed since the token was sent, in which case nothing is verified.
+    let verified = crate::sql::account::mark_email_verified(
+        env.database(),
+        verification.account,
+        &verification.email,
+    )
+    .await?;
+
+    if verified == 0 {
+        return Err(AuthError::InvalidCredentials.into());
+    }
+
+    Ok(())
+}
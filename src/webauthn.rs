@@ -0,0 +1,304 @@
+use crate::{
+    auth::AuthError,
+    environment::{webauthn, Environment},
+    helpers::cache,
+    session::Session,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Seconds a browser may take to answer a ceremony.
+const CEREMONY_LIFETIME: usize = 300;
+
+/// Ceremony state kept in Redis under its challenge.
+#[derive(Serialize, Deserialize, Debug)]
+struct Ceremony {
+    account: Option<Uuid>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RelyingParty {
+    id: String,
+    name: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct User {
+    id: String,
+    name: String,
+    display_name: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialParameters {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    alg: i64,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    id: String,
+}
+
+/// `PublicKeyCredentialCreationOptions` for `navigator.credentials.create`.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CreationOptions {
+    challenge: String,
+    rp: RelyingParty,
+    user: User,
+    pub_key_cred_params: Vec<CredentialParameters>,
+    timeout: usize,
+    attestation: &'static str,
+    exclude_credentials: Vec<CredentialDescriptor>,
+}
+
+/// `PublicKeyCredentialRequestOptions` for `navigator.credentials.get`.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestOptions {
+    challenge: String,
+    rp_id: String,
+    timeout: usize,
+    user_verification: &'static str,
+    allow_credentials: Vec<CredentialDescriptor>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    client_data_json: String,
+    #[serde(rename = "attestationObject")]
+    attestation_object: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Registration {
+    response: AttestationResponse,
+    name: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LoginRequest {
+    email: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    client_data_json: String,
+    #[serde(rename = "authenticatorData")]
+    authenticator_data: String,
+    signature: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Assertion {
+    id: String,
+    response: AssertionResponse,
+    pub lifetime: Option<i64>,
+}
+
+fn descriptors(ids: Vec<String>) -> Vec<CredentialDescriptor> {
+    ids.into_iter()
+        .map(|id| CredentialDescriptor {
+            kind: "public-key",
+            id,
+        })
+        .collect()
+}
+
+async fn begin(env: &Environment, kind: &str, ceremony: &Ceremony) -> anyhow::Result<String> {
+    let challenge = env.webauthn().generate_challenge();
+    cache::set_ex(
+        &mut env.redis().await?,
+        format!("webauthn:{}:{}", kind, challenge),
+        ceremony,
+        CEREMONY_LIFETIME,
+    )
+    .await?;
+
+    Ok(challenge)
+}
+
+/// Takes the ceremony answered by the client data, so every challenge is used at most once.
+async fn finish(env: &Environment, kind: &str, client_data: &[u8]) -> anyhow::Result<Ceremony> {
+    let challenge = env
+        .webauthn()
+        .challenge(client_data, &format!("webauthn.{}", kind))?;
+    let key = format!("webauthn:{}:{}", kind, challenge);
+
+    Ok(cache::take(&mut env.redis().await?, &key)
+        .await
+        .or(Err(AuthError::InvalidCredentials))?)
+}
+
+pub async fn begin_registration(
+    env: &Environment,
+    session: &Session,
+) -> anyhow::Result<CreationOptions> {
+    let account = session.account().await?;
+    let challenge = begin(
+        env,
+        "create",
+        &Ceremony {
+            account: Some(account.id),
+        },
+    )
+    .await?;
+
+    let credentials = crate::sql::webauthn::get_credential_ids(env.database(), account.id).await?;
+
+    Ok(CreationOptions {
+        challenge,
+        rp: RelyingParty {
+            id: env.webauthn().rp_id().to_owned(),
+            name: env.webauthn().rp_name().to_owned(),
+        },
+        user: User {
+            id: webauthn::encode(account.id.as_bytes()),
+            name: account.email.to_owned(),
+            display_name: account.email,
+        },
+        pub_key_cred_params: webauthn::ALGORITHMS
+            .iter()
+            .map(|alg| CredentialParameters {
+                kind: "public-key",
+                alg: *alg,
+            })
+            .collect(),
+        timeout: CEREMONY_LIFETIME * 1000,
+        attestation: "none",
+        exclude_credentials: descriptors(credentials),
+    })
+}
+
+pub async fn finish_registration(
+    env: &Environment,
+    session: &Session,
+    registration: Registration,
+) -> anyhow::Result<()> {
+    let client_data = webauthn::decode(&registration.response.client_data_json)?;
+    let ceremony = finish(env, "create", &client_data).await?;
+    if ceremony.account != Some(session.account_id()) {
+        return Err(AuthError::InvalidCredentials.into());
+    }
+
+    let attestation_object = webauthn::decode(&registration.response.attestation_object)?;
+    let credential = env.webauthn().register(&attestation_object)?;
+
+    crate::sql::webauthn::create_credential(
+        env.database(),
+        Uuid::new_v4(),
+        session.account_id(),
+        &credential.id,
+        &credential.public_key,
+        credential.sign_count as i64,
+        registration.name.as_deref(),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Starts a login, limited to the credentials of `email` when given and open to any discoverable
+/// credential otherwise.
+pub async fn begin_login(env: &Environment, req: LoginRequest) -> anyhow::Result<RequestOptions> {
+    let account = match req.email {
+        Some(email) => {
+            crate::sql::account::get_account_id_password_by_email(env.database(), &email)
+                .await?
+                .map(|account| account.id)
+        }
+        None => None,
+    };
+    let credentials = match account {
+        Some(account) => crate::sql::webauthn::get_credential_ids(env.database(), account).await?,
+        None => Vec::new(),
+    };
+
+    // Unknown emails get a challenge as well, so they can't be told apart from known ones.
+    let challenge = begin(env, "get", &Ceremony { account }).await?;
+
+    Ok(RequestOptions {
+        challenge,
+        rp_id: env.webauthn().rp_id().to_owned(),
+        timeout: CEREMONY_LIFETIME * 1000,
+        user_verification: "preferred",
+        allow_credentials: descriptors(credentials),
+    })
+}
+
+/// Verifies an assertion and returns the account it logs into, along with whether the user was
+/// verified. Only a user-verifying passkey counts as both factors, a mere touch of a security key
+/// still needs the TOTP challenge.
+pub async fn finish_login(
+    env: &Environment,
+    assertion: &Assertion,
+) -> anyhow::Result<(Uuid, bool)> {
+    let client_data = webauthn::decode(&assertion.response.client_data_json)?;
+    let ceremony = finish(env, "get", &client_data).await?;
+
+    let credential_id = webauthn::encode(&webauthn::decode(&assertion.id)?);
+    let credential = crate::sql::webauthn::get_credential(env.database(), &credential_id)
+        .await?
+        .ok_or(AuthError::InvalidCredentials)?;
+    if ceremony
+        .account
+        .map_or(false, |account| account != credential.account)
+    {
+        return Err(AuthError::InvalidCredentials.into());
+    }
+
+    let verification = env.webauthn().verify(
+        &credential.public_key,
+        &webauthn::decode(&assertion.response.authenticator_data)?,
+        &client_data,
+        &webauthn::decode(&assertion.response.signature)?,
+    )?;
+    let sign_count = verification.sign_count as i64;
+
+    // Checked before the use is recorded, so a locked or disabled account leaves it untouched.
+    // Like a password login, the lock is answered as invalid credentials.
+    if let Some(locked_until) = credential.locked_until {
+        if locked_until > Utc::now() {
+            return Err(AuthError::InvalidCredentials.into());
+        }
+    }
+    if credential.disabled_at.is_some() {
+        return Err(AuthError::AccountDisabled.into());
+    }
+    if env.require_verified_email() && credential.email_verified_at.is_none() {
+        return Err(AuthError::EmailNotVerified.into());
+    }
+
+    // A counter that doesn't move forward means the authenticator may have been cloned.
+    if (sign_count != 0 || credential.sign_count != 0) && sign_count <= credential.sign_count {
+        tracing::warn!(
+            "webauthn sign count regressed for credential {}",
+            credential.id
+        );
+        return Err(AuthError::InvalidCredentials.into());
+    }
+    if crate::sql::webauthn::record_credential_use(
+        env.database(),
+        credential.id,
+        credential.sign_count,
+        sign_count,
+    )
+    .await?
+        == 0
+    {
+        return Err(AuthError::InvalidCredentials.into());
+    }
+
+    Ok((credential.account, verification.user_verified))
+}